@@ -26,27 +26,27 @@ fn main() {
     let mkl_bindings = bindgen::Builder::default()
       .clang_arg(format!("-I{}", mkl_dir.join("include").as_os_str().to_str().unwrap()))
       .header("wrapped.h")
-      .whitelist_type("CBLAS_LAYOUT")
-      .whitelist_type("CBLAS_TRANSPOSE")
-      .whitelist_type("CBLAS_UPLO")
-      .whitelist_type("CBLAS_DIAG")
-      .whitelist_type("CBLAS_SIDE")
-      .whitelist_type("CBLAS_STORAGE")
-      .whitelist_type("CBLAS_IDENTIFIER")
-      .whitelist_type("CBLAS_OFFSET")
-      .whitelist_type("CBLAS_ORDER")
-      .whitelist_function("cblas_sdot")
-      .whitelist_function("cblas_ddot")
-      .whitelist_function("cblas_snrm2")
-      .whitelist_function("cblas_dnrm2")
-      .whitelist_function("cblas_saxpy")
-      .whitelist_function("cblas_daxpy")
-      .whitelist_function("cblas_sscal")
-      .whitelist_function("cblas_dscal")
-      .whitelist_function("cblas_sgemv")
-      .whitelist_function("cblas_dgemv")
-      .whitelist_function("cblas_sgemm")
-      .whitelist_function("cblas_dgemm")
+      .allowlist_type("CBLAS_LAYOUT")
+      .allowlist_type("CBLAS_TRANSPOSE")
+      .allowlist_type("CBLAS_UPLO")
+      .allowlist_type("CBLAS_DIAG")
+      .allowlist_type("CBLAS_SIDE")
+      .allowlist_type("CBLAS_STORAGE")
+      .allowlist_type("CBLAS_IDENTIFIER")
+      .allowlist_type("CBLAS_OFFSET")
+      .allowlist_type("CBLAS_ORDER")
+      .allowlist_function("cblas_sdot")
+      .allowlist_function("cblas_ddot")
+      .allowlist_function("cblas_snrm2")
+      .allowlist_function("cblas_dnrm2")
+      .allowlist_function("cblas_saxpy")
+      .allowlist_function("cblas_daxpy")
+      .allowlist_function("cblas_sscal")
+      .allowlist_function("cblas_dscal")
+      .allowlist_function("cblas_sgemv")
+      .allowlist_function("cblas_dgemv")
+      .allowlist_function("cblas_sgemm")
+      .allowlist_function("cblas_dgemm")
       .generate()
       .expect("bindgen failed to generate mkl bindings");
     mkl_bindings
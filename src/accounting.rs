@@ -0,0 +1,281 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::fmt;
+use std::sync::{Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const UNTAGGED: &'static str = "untagged";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry{
+  epoch:    0,
+  total:    MemStats{live_bytes: 0, peak_bytes: 0, alloc_count: 0, free_count: 0},
+  tags:     Vec::new(),
+});
+
+/// Starts counting allocations made by every `Mem` backend in this crate.
+/// Memory allocated while accounting is disabled is never counted, even
+/// when it is freed later.
+pub fn enable() {
+  ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn disable() {
+  ENABLED.store(false, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MemStats {
+  pub live_bytes:   usize,
+  pub peak_bytes:   usize,
+  pub alloc_count:  usize,
+  pub free_count:   usize,
+}
+
+impl MemStats {
+  fn add_live(&mut self, bytes: usize) {
+    self.live_bytes += bytes;
+    if self.live_bytes > self.peak_bytes {
+      self.peak_bytes = self.live_bytes;
+    }
+  }
+}
+
+struct Registry {
+  // Bumped by `reset`, so that frees and retags of allocations counted
+  // before it are ignored.
+  epoch:    u64,
+  total:    MemStats,
+  tags:     Vec<(&'static str, MemStats)>,
+}
+
+impl Registry {
+  fn tag_mut(&mut self, tag: &'static str) -> &mut MemStats {
+    match self.tags.iter().position(|&(t, _)| t == tag) {
+      Some(pos) => &mut self.tags[pos].1,
+      None => {
+        self.tags.push((tag, MemStats::default()));
+        &mut self.tags.last_mut().unwrap().1
+      }
+    }
+  }
+}
+
+fn record_alloc(tag: &'static str, bytes: usize) -> u64 {
+  let mut reg = REGISTRY.lock().unwrap();
+  reg.total.add_live(bytes);
+  reg.total.alloc_count += 1;
+  let stats = reg.tag_mut(tag);
+  stats.add_live(bytes);
+  stats.alloc_count += 1;
+  reg.epoch
+}
+
+fn record_free(epoch: u64, tag: &'static str, bytes: usize) {
+  let mut reg = REGISTRY.lock().unwrap();
+  if epoch != reg.epoch {
+    return;
+  }
+  reg.total.live_bytes -= bytes;
+  reg.total.free_count += 1;
+  let stats = reg.tag_mut(tag);
+  stats.live_bytes -= bytes;
+  stats.free_count += 1;
+}
+
+// Retagging counts as a free under the old tag and an allocation under the
+// new one; the totals are unchanged.
+fn record_retag(epoch: u64, old_tag: &'static str, new_tag: &'static str, bytes: usize) {
+  let mut reg = REGISTRY.lock().unwrap();
+  if epoch != reg.epoch {
+    return;
+  }
+  {
+    let old_stats = reg.tag_mut(old_tag);
+    old_stats.live_bytes -= bytes;
+    old_stats.free_count += 1;
+  }
+  let new_stats = reg.tag_mut(new_tag);
+  new_stats.add_live(bytes);
+  new_stats.alloc_count += 1;
+}
+
+/// Returns the totals over every tag.
+pub fn total() -> MemStats {
+  REGISTRY.lock().unwrap().total
+}
+
+pub fn tag_stats(tag: &'static str) -> MemStats {
+  let reg = REGISTRY.lock().unwrap();
+  reg.tags.iter().find(|&&(t, _)| t == tag).map(|&(_, stats)| stats).unwrap_or_default()
+}
+
+/// Clears every counter, including live bytes. Memory counted before the
+/// reset is no longer counted when it is retagged or freed.
+pub fn reset() {
+  let mut reg = REGISTRY.lock().unwrap();
+  reg.epoch += 1;
+  reg.total = MemStats::default();
+  reg.tags.clear();
+}
+
+pub fn report() -> MemReport {
+  let reg = REGISTRY.lock().unwrap();
+  MemReport{
+    total:  reg.total,
+    tags:   reg.tags.clone(),
+  }
+}
+
+/// Prints `report()` to stderr.
+pub fn dump_report() {
+  eprint!("{}", report());
+}
+
+#[derive(Clone, Debug)]
+pub struct MemReport {
+  pub total:    MemStats,
+  pub tags:     Vec<(&'static str, MemStats)>,
+}
+
+impl fmt::Display for MemReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{:<24} {:>16} {:>16} {:>10} {:>10}", "tag", "live bytes", "peak bytes", "allocs", "frees")?;
+    for &(tag, ref stats) in self.tags.iter() {
+      writeln!(f, "{:<24} {:>16} {:>16} {:>10} {:>10}",
+          tag, stats.live_bytes, stats.peak_bytes, stats.alloc_count, stats.free_count)?;
+    }
+    writeln!(f, "{:<24} {:>16} {:>16} {:>10} {:>10}",
+        "(total)", self.total.live_bytes, self.total.peak_bytes, self.total.alloc_count, self.total.free_count)
+  }
+}
+
+/// The accounting record for a single allocation. `Mem` backends hold one
+/// of these alongside the memory it describes; dropping it records the free.
+pub struct MemAccount {
+  tag:      &'static str,
+  bytes:    usize,
+  // The registry epoch when the allocation was counted, if it was.
+  epoch:    Option<u64>,
+}
+
+impl Drop for MemAccount {
+  fn drop(&mut self) {
+    if let Some(epoch) = self.epoch {
+      record_free(epoch, self.tag, self.bytes);
+    }
+  }
+}
+
+impl MemAccount {
+  pub fn new(bytes: usize) -> Self {
+    let epoch = if is_enabled() {
+      Some(record_alloc(UNTAGGED, bytes))
+    } else {
+      None
+    };
+    MemAccount{
+      tag:      UNTAGGED,
+      bytes:    bytes,
+      epoch:    epoch,
+    }
+  }
+
+  pub fn tag(&self) -> &'static str {
+    self.tag
+  }
+
+  pub fn set_tag(&mut self, tag: &'static str) {
+    if let Some(epoch) = self.epoch {
+      if tag != self.tag {
+        record_retag(epoch, self.tag, tag, self.bytes);
+      }
+    }
+    self.tag = tag;
+  }
+}
+
+/// Memory whose allocation can be attributed to a tag, e.g. "weights" or
+/// "activations".
+pub trait TagMem {
+  fn tag(&self) -> &'static str;
+  fn set_tag(&mut self, tag: &'static str);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Other tests allocate memory concurrently, so only the stats of the tags
+  // used here are checked, and accounting stays enabled throughout.
+  #[test]
+  fn tags_live_peak_retag_reset() {
+    enable();
+    assert!(is_enabled());
+    let mut a = MemAccount::new(100);
+    a.set_tag("acct-test-a");
+    {
+      let mut b = MemAccount::new(50);
+      b.set_tag("acct-test-a");
+      let stats = tag_stats("acct-test-a");
+      assert_eq!(stats.live_bytes, 150);
+      assert_eq!(stats.peak_bytes, 150);
+      assert_eq!(stats.alloc_count, 2);
+      b.set_tag("acct-test-b");
+      let stats = tag_stats("acct-test-a");
+      assert_eq!(stats.live_bytes, 100);
+      assert_eq!(stats.peak_bytes, 150);
+      assert_eq!(stats.free_count, 1);
+      let stats = tag_stats("acct-test-b");
+      assert_eq!(stats.live_bytes, 50);
+      assert_eq!(stats.alloc_count, 1);
+    }
+    let stats = tag_stats("acct-test-b");
+    assert_eq!(stats.live_bytes, 0);
+    assert_eq!(stats.peak_bytes, 50);
+    assert_eq!(stats.free_count, 1);
+    assert!(report().tags.iter().any(|&(tag, _)| tag == "acct-test-a"));
+
+    // Counted before the reset, so retagging and freeing it is ignored.
+    reset();
+    a.set_tag("acct-test-b");
+    drop(a);
+    let stats = tag_stats("acct-test-a");
+    assert_eq!((stats.live_bytes, stats.alloc_count, stats.free_count), (0, 0, 0));
+    let stats = tag_stats("acct-test-b");
+    assert_eq!((stats.live_bytes, stats.alloc_count, stats.free_count), (0, 0, 0));
+
+    // Not counted while disabled, even when freed after enabling again.
+    disable();
+    let mut c = MemAccount::new(10);
+    c.set_tag("acct-test-c");
+    enable();
+    drop(c);
+    let stats = tag_stats("acct-test-c");
+    assert_eq!((stats.live_bytes, stats.alloc_count, stats.free_count), (0, 0, 0));
+    let mut d = MemAccount::new(10);
+    d.set_tag("acct-test-c");
+    drop(d);
+    let stats = tag_stats("acct-test-c");
+    assert_eq!((stats.live_bytes, stats.peak_bytes, stats.alloc_count, stats.free_count), (0, 10, 1, 1));
+    disable();
+  }
+}
@@ -0,0 +1,162 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{MemArrayViewMut, ZeroBits};
+use accounting::{MemAccount, TagMem};
+
+use arrayidx::{ArrayIndex};
+
+use std::alloc::{Layout, alloc, dealloc};
+use std::cell::{Cell};
+use std::mem::{align_of, size_of};
+use std::ptr::{write_bytes};
+
+const ARENA_ALIGN: usize = 64;
+
+/// A bump allocator for temporary arrays which all die together. Views
+/// handed out by the arena borrow it, so `reset` (which takes `&mut self`)
+/// can only be called once every view is gone.
+pub struct ScratchArena {
+  base:     *mut u8,
+  capacity: usize,
+  used:     Cell<usize>,
+  acct:     MemAccount,
+}
+
+unsafe impl Send for ScratchArena {}
+
+impl Drop for ScratchArena {
+  fn drop(&mut self) {
+    unsafe { dealloc(self.base, ScratchArena::layout(self.capacity)) };
+  }
+}
+
+impl TagMem for ScratchArena {
+  fn tag(&self) -> &'static str {
+    self.acct.tag()
+  }
+
+  fn set_tag(&mut self, tag: &'static str) {
+    self.acct.set_tag(tag);
+  }
+}
+
+impl ScratchArena {
+  fn layout(capacity: usize) -> Layout {
+    Layout::from_size_align(capacity.max(1), ARENA_ALIGN).unwrap()
+  }
+
+  /// Allocates a single block of `capacity` bytes up front.
+  pub fn new(capacity: usize) -> Self {
+    let base = unsafe { alloc(ScratchArena::layout(capacity)) };
+    assert!(!base.is_null());
+    ScratchArena{
+      base:     base,
+      capacity: capacity,
+      used:     Cell::new(0),
+      acct:     MemAccount::new(capacity),
+    }
+  }
+
+  pub fn with_tag(mut self, tag: &'static str) -> Self {
+    self.set_tag(tag);
+    self
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn used(&self) -> usize {
+    self.used.get()
+  }
+
+  /// Releases every array carved from the arena in O(1).
+  pub fn reset(&mut self) {
+    self.used.set(0);
+  }
+
+  fn bump(&self, phsz: usize, align: usize) -> Option<*mut u8> {
+    let base = self.base as usize;
+    let start = base.checked_add(self.used.get())?.checked_add(align - 1)? & !(align - 1);
+    let end = start.checked_add(phsz)?;
+    if end > base + self.capacity {
+      return None;
+    }
+    self.used.set(end - base);
+    Some(start as *mut u8)
+  }
+
+  /// Carves a zeroed, packed array from the arena, or returns `None` if the
+  /// arena does not have enough space left.
+  pub fn try_zeros<'a, Idx, T>(&'a self, size: Idx) -> Option<MemArrayViewMut<'a, Idx, T>>
+  where Idx: ArrayIndex, T: ZeroBits + 'static,
+  {
+    let len = size.flat_len();
+    let phsz = len.checked_mul(size_of::<T>())?;
+    let buf = self.bump(phsz, align_of::<T>())? as *mut T;
+    unsafe { write_bytes::<T>(buf, 0, len) };
+    let stride = size.to_packed_stride();
+    // The bump pointer only moves forward until `reset`, which needs
+    // `&mut self`, so the block is not handed out again while `'a` lasts.
+    Some(unsafe { MemArrayViewMut::from_raw(size, 0, stride, buf, len) })
+  }
+
+  pub fn zeros<'a, Idx, T>(&'a self, size: Idx) -> MemArrayViewMut<'a, Idx, T>
+  where Idx: ArrayIndex, T: ZeroBits + 'static,
+  {
+    let used = self.used();
+    match self.try_zeros(size.clone()) {
+      None => panic!("ScratchArena: out of space: capacity: {} used: {} requested size: {:?}",
+          self.capacity, used, size),
+      Some(view) => view,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn arena_views_are_disjoint() {
+    let mut arena = ScratchArena::new(1024);
+    {
+      let mut a = arena.zeros::<_, f32>([4, 4]);
+      let mut b = arena.zeros::<_, f64>(8);
+      // Only the data is carved from the arena.
+      assert_eq!(arena.used(), 16 * 4 + 8 * 8);
+      a.set([3, 3], 1.0);
+      b.set(0, 2.0);
+      assert_eq!(a[[3, 3]], 1.0);
+      assert_eq!(b[0], 2.0);
+      assert_eq!(a.flat_slice_mut().unwrap().iter().filter(|&&x| x != 0.0).count(), 1);
+    }
+    arena.reset();
+    assert_eq!(arena.used(), 0);
+    let c = arena.zeros::<_, f32>([4, 4]);
+    assert!(c.flat_slice().unwrap().iter().all(|&x| x == 0.0));
+  }
+
+  #[test]
+  fn arena_out_of_space() {
+    let arena = ScratchArena::new(64);
+    assert!(arena.try_zeros::<_, u8>(64).is_some());
+    assert!(arena.try_zeros::<_, u8>(1).is_none());
+    assert!(arena.try_zeros::<_, u64>(usize::max_value()).is_none());
+    assert_eq!(arena.used(), 64);
+  }
+}
@@ -0,0 +1,656 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{MemArray, MemArrayView, MemArrayViewMut, Mem, ReadOnlyMem, ZeroBits};
+
+use arrayidx::*;
+
+use std::marker::{PhantomData};
+
+/// Index types which have an axis to remove, e.g. `Index3d` to `Index2d`.
+pub trait RemoveAxis: ArrayIndex {
+  type Lower: ArrayIndex;
+}
+
+/// Index types which have room for another axis, e.g. `Index2d` to
+/// `Index3d`.
+pub trait InsertAxis: ArrayIndex {
+  type Higher: ArrayIndex;
+}
+
+impl RemoveAxis for Index1d { type Lower = Index0d; }
+impl RemoveAxis for Index2d { type Lower = Index1d; }
+impl RemoveAxis for Index3d { type Lower = Index2d; }
+impl RemoveAxis for Index4d { type Lower = Index3d; }
+impl RemoveAxis for Index5d { type Lower = Index4d; }
+
+impl InsertAxis for Index0d { type Higher = Index1d; }
+impl InsertAxis for Index1d { type Higher = Index2d; }
+impl InsertAxis for Index2d { type Higher = Index3d; }
+impl InsertAxis for Index3d { type Higher = Index4d; }
+impl InsertAxis for Index4d { type Higher = Index5d; }
+
+fn index_axis_layout<Idx>(size: &Idx, stride: &Idx, offset: usize, axis: usize, i: usize) -> (Idx::Lower, Idx::Lower, usize)
+where Idx: RemoveAxis,
+{
+  let (mut size, mut stride) = (size.to_nd(), stride.to_nd());
+  assert!(axis < size.len(), "index_axis: axis out of bounds: axis: {} ndim: {}", axis, size.len());
+  assert!(i < size[axis], "index_axis: index out of bounds: index: {} axis: {} len: {}", i, axis, size[axis]);
+  let offset = offset.wrapping_add(i.wrapping_mul(stride[axis]));
+  size.remove(axis);
+  stride.remove(axis);
+  (Idx::Lower::from_nd(size), Idx::Lower::from_nd(stride), offset)
+}
+
+fn insert_axis_layout<Idx>(size: &Idx, stride: &Idx, axis: usize) -> (Idx::Higher, Idx::Higher)
+where Idx: InsertAxis,
+{
+  let (mut size, mut stride) = (size.to_nd(), stride.to_nd());
+  assert!(axis <= size.len(), "insert_axis: axis out of bounds: axis: {} ndim: {}", axis, size.len());
+  // The new axis gets the stride it would have in a packed array, so that
+  // packed arrays stay packed.
+  let new_stride = if axis == 0 { 1 } else { stride[axis - 1].wrapping_mul(size[axis - 1]) };
+  size.insert(axis, 1);
+  stride.insert(axis, new_stride);
+  (Idx::Higher::from_nd(size), Idx::Higher::from_nd(stride))
+}
+
+fn squeeze_layout<Idx, NewIdx>(size: &Idx, stride: &Idx) -> (NewIdx, NewIdx)
+where Idx: ArrayIndex, NewIdx: ArrayIndex,
+{
+  let (new_size, new_stride): (Vec<usize>, Vec<usize>) = size.to_nd().into_iter().zip(stride.to_nd().into_iter())
+    .filter(|&(s, _)| s != 1)
+    .unzip();
+  assert_eq!(new_size.len(), NewIdx::zero().to_nd().len(),
+      "squeeze: rank mismatch: size: {:?} squeezed size: {:?}", size, new_size);
+  (NewIdx::from_nd(new_size), NewIdx::from_nd(new_stride))
+}
+
+impl<'a, Idx, T> MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  /// Returns the subview at index `i` along `axis`, which is one rank lower.
+  pub fn index_axis(self, axis: usize, i: usize) -> MemArrayView<'a, Idx::Lower, T> where Idx: RemoveAxis {
+    let (size, stride, offset) = index_axis_layout(&self.size, &self.stride, self.offset, axis, i);
+    MemArrayView{
+      size:     size,
+      offset:   offset,
+      stride:   stride,
+      mem:      self.mem,
+    }
+  }
+
+  /// Returns a view with a new axis of length 1 before `axis`.
+  pub fn insert_axis(self, axis: usize) -> MemArrayView<'a, Idx::Higher, T> where Idx: InsertAxis {
+    let (size, stride) = insert_axis_layout(&self.size, &self.stride, axis);
+    MemArrayView{
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      mem:      self.mem,
+    }
+  }
+
+  /// Returns a view without the axes of length 1. Panics if the remaining
+  /// axes do not match the rank of `NewIdx`.
+  pub fn squeeze<NewIdx>(self) -> MemArrayView<'a, NewIdx, T> where NewIdx: ArrayIndex {
+    let (size, stride) = squeeze_layout(&self.size, &self.stride);
+    MemArrayView{
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      mem:      self.mem,
+    }
+  }
+
+  /// Splits the view along `axis` into consecutive parts with the lengths in
+  /// `sizes`, which must add up to the length of the axis.
+  pub fn split(self, axis: usize, sizes: &[usize]) -> Vec<MemArrayView<'a, Idx, T>> {
+    let size = self.size.to_nd();
+    assert!(axis < size.len(), "split: axis out of bounds: axis: {} ndim: {}", axis, size.len());
+    assert_eq!(sizes.iter().sum::<usize>(), size[axis],
+        "split: size mismatch: sizes: {:?} axis: {} len: {}", sizes, axis, size[axis]);
+    let stride = self.stride.to_nd();
+    let mut parts = Vec::with_capacity(sizes.len());
+    let mut start: usize = 0;
+    for &len in sizes {
+      let mut part_size = size.clone();
+      part_size[axis] = len;
+      parts.push(MemArrayView{
+        size:     Idx::from_nd(part_size),
+        offset:   self.offset.wrapping_add(start.wrapping_mul(stride[axis])),
+        stride:   self.stride.clone(),
+        mem:      self.mem,
+      });
+      start += len;
+    }
+    parts
+  }
+}
+
+impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  pub fn index_axis_mut(self, axis: usize, i: usize) -> MemArrayViewMut<'a, Idx::Lower, T> where Idx: RemoveAxis {
+    let (size, stride, offset) = index_axis_layout(&self.size, &self.stride, self.offset, axis, i);
+    MemArrayViewMut{
+      size:     size,
+      offset:   offset,
+      stride:   stride,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+
+  pub fn insert_axis_mut(self, axis: usize) -> MemArrayViewMut<'a, Idx::Higher, T> where Idx: InsertAxis {
+    let (size, stride) = insert_axis_layout(&self.size, &self.stride, axis);
+    MemArrayViewMut{
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+
+  pub fn squeeze_mut<NewIdx>(self) -> MemArrayViewMut<'a, NewIdx, T> where NewIdx: ArrayIndex {
+    let (size, stride) = squeeze_layout(&self.size, &self.stride);
+    MemArrayViewMut{
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyMem<T> {
+  pub fn index_axis<'a>(&'a self, axis: usize, i: usize) -> MemArrayView<'a, Idx::Lower, T> where Idx: RemoveAxis {
+    self.as_view().index_axis(axis, i)
+  }
+
+  pub fn insert_axis<'a>(&'a self, axis: usize) -> MemArrayView<'a, Idx::Higher, T> where Idx: InsertAxis {
+    self.as_view().insert_axis(axis)
+  }
+
+  pub fn squeeze<'a, NewIdx>(&'a self) -> MemArrayView<'a, NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().squeeze()
+  }
+
+  pub fn split<'a>(&'a self, axis: usize, sizes: &[usize]) -> Vec<MemArrayView<'a, Idx, T>> {
+    self.as_view().split(axis, sizes)
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: Mem<T> {
+  pub fn index_axis_mut<'a>(&'a mut self, axis: usize, i: usize) -> MemArrayViewMut<'a, Idx::Lower, T> where Idx: RemoveAxis {
+    self.as_view_mut().index_axis_mut(axis, i)
+  }
+
+  pub fn insert_axis_mut<'a>(&'a mut self, axis: usize) -> MemArrayViewMut<'a, Idx::Higher, T> where Idx: InsertAxis {
+    self.as_view_mut().insert_axis_mut(axis)
+  }
+
+  pub fn squeeze_mut<'a, NewIdx>(&'a mut self) -> MemArrayViewMut<'a, NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view_mut().squeeze_mut()
+  }
+}
+
+impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  // A view of `len` elements starting at index `start` along `axis`. Only
+  // used to carve disjoint parts out of `self`.
+  //
+  // Unsafe because the part outlives the borrow of `self`: the caller must
+  // not access the elements of the part through `self` or through another
+  // overlapping part while it is alive.
+  unsafe fn axis_part(&self, axis: usize, start: usize, len: usize) -> MemArrayViewMut<'a, Idx, T> {
+    let mut size = self.size.to_nd();
+    let stride = self.stride.to_nd();
+    size[axis] = len;
+    MemArrayViewMut{
+      size:     Idx::from_nd(size),
+      offset:   self.offset.wrapping_add(start.wrapping_mul(stride[axis])),
+      stride:   self.stride.clone(),
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+
+  /// Splits the view in two along `axis`, before index `i`. The two parts
+  /// do not overlap, and may be used (or sent to other threads) at the same
+  /// time.
+  pub fn split_at_mut(self, axis: usize, i: usize) -> (MemArrayViewMut<'a, Idx, T>, MemArrayViewMut<'a, Idx, T>) {
+    let size = self.size.to_nd();
+    assert!(axis < size.len(), "split_at_mut: axis out of bounds: axis: {} ndim: {}", axis, size.len());
+    assert!(i <= size[axis], "split_at_mut: index out of bounds: index: {} axis: {} len: {}", i, axis, size[axis]);
+    // The parts are disjoint, and `self` is consumed.
+    unsafe { (self.axis_part(axis, 0, i), self.axis_part(axis, i, size[axis] - i)) }
+  }
+
+  /// Returns an iterator over non-overlapping parts of the view, each of
+  /// length `n` along `axis`, except possibly the last.
+  pub fn chunks_mut(self, axis: usize, n: usize) -> AxisChunksIterMut<'a, Idx, T> {
+    let size = self.size.to_nd();
+    assert!(axis < size.len(), "chunks_mut: axis out of bounds: axis: {} ndim: {}", axis, size.len());
+    assert!(n > 0, "chunks_mut: zero chunk length");
+    AxisChunksIterMut{
+      axis:     axis,
+      chunk:    n,
+      pos:      0,
+      end:      size[axis],
+      view:     self,
+    }
+  }
+
+  /// Returns an iterator over the subviews at each index along `axis`, as
+  /// in `index_axis_mut`.
+  pub fn axis_iter_mut(self, axis: usize) -> AxisIterMut<'a, Idx, T> where Idx: RemoveAxis {
+    let size = self.size.to_nd();
+    assert!(axis < size.len(), "axis_iter_mut: axis out of bounds: axis: {} ndim: {}", axis, size.len());
+    AxisIterMut{
+      axis:     axis,
+      pos:      0,
+      end:      size[axis],
+      view:     self,
+    }
+  }
+}
+
+pub struct AxisChunksIterMut<'a, Idx, T> where T: Copy + 'static {
+  axis:     usize,
+  chunk:    usize,
+  pos:      usize,
+  end:      usize,
+  view:     MemArrayViewMut<'a, Idx, T>,
+}
+
+impl<'a, Idx, T> Iterator for AxisChunksIterMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  type Item = MemArrayViewMut<'a, Idx, T>;
+
+  fn next(&mut self) -> Option<MemArrayViewMut<'a, Idx, T>> {
+    if self.pos >= self.end {
+      return None;
+    }
+    let len = self.chunk.min(self.end - self.pos);
+    // Each chunk starts where the last one ended, and `self.view` is
+    // private to the iterator.
+    let part = unsafe { self.view.axis_part(self.axis, self.pos, len) };
+    self.pos += len;
+    Some(part)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let n = (self.end - self.pos + self.chunk - 1) / self.chunk;
+    (n, Some(n))
+  }
+}
+
+impl<'a, Idx, T> ExactSizeIterator for AxisChunksIterMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+}
+
+pub struct AxisIterMut<'a, Idx, T> where T: Copy + 'static {
+  axis:     usize,
+  pos:      usize,
+  end:      usize,
+  view:     MemArrayViewMut<'a, Idx, T>,
+}
+
+impl<'a, Idx, T> Iterator for AxisIterMut<'a, Idx, T> where Idx: RemoveAxis, T: Copy + 'static {
+  type Item = MemArrayViewMut<'a, Idx::Lower, T>;
+
+  fn next(&mut self) -> Option<MemArrayViewMut<'a, Idx::Lower, T>> {
+    if self.pos >= self.end {
+      return None;
+    }
+    let (size, stride, offset) = index_axis_layout(&self.view.size, &self.view.stride, self.view.offset, self.axis, self.pos);
+    self.pos += 1;
+    Some(MemArrayViewMut{
+      size:     size,
+      offset:   offset,
+      stride:   stride,
+      ptr:      self.view.ptr,
+      mem_len:  self.view.mem_len,
+      _mrk:     PhantomData,
+    })
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let n = self.end - self.pos;
+    (n, Some(n))
+  }
+}
+
+impl<'a, Idx, T> ExactSizeIterator for AxisIterMut<'a, Idx, T> where Idx: RemoveAxis, T: Copy + 'static {
+}
+
+// Checks that `views` have the same size except along `axis`, and returns
+// the size of their concatenation.
+fn concat_size<Idx, T>(name: &str, axis: usize, views: &[MemArrayView<Idx, T>]) -> Vec<usize>
+where Idx: ArrayIndex, T: Copy + 'static,
+{
+  assert!(!views.is_empty(), "{}: no inputs", name);
+  let mut size = views[0].size.to_nd();
+  assert!(axis < size.len(), "{}: axis out of bounds: axis: {} ndim: {}", name, axis, size.len());
+  for (i, view) in views.iter().enumerate().skip(1) {
+    let view_size = view.size.to_nd();
+    for d in 0 .. size.len() {
+      if d != axis && view_size[d] != size[d] {
+        panic!("{}: size mismatch: input {}: size: {:?} input 0 size: {:?} axis: {}",
+            name, i, view.size, views[0].size, axis);
+      }
+    }
+    size[axis] += view_size[axis];
+  }
+  size
+}
+
+/// Copies `views` one after another along `axis` into `dst`. The views must
+/// have the same size except along `axis`, and `dst` their combined size.
+pub fn concatenate_into<Idx, T>(axis: usize, views: &[MemArrayView<Idx, T>], dst: &mut MemArrayViewMut<Idx, T>)
+where Idx: ArrayIndex, T: Copy + 'static,
+{
+  let size = concat_size("concatenate_into", axis, views);
+  assert!(dst.size.to_nd() == size,
+      "concatenate_into: size mismatch: dst size: {:?} expected size: {:?}", dst.size, size);
+  let mut start = 0;
+  for view in views {
+    let len = view.size.to_nd()[axis];
+    // The part is dropped before `dst` is used again.
+    unsafe { dst.axis_part(axis, start, len) }.copy_from(view);
+    start += len;
+  }
+}
+
+/// Like `concatenate_into`, into a new packed array.
+pub fn concatenate<Idx, T>(axis: usize, views: &[MemArrayView<Idx, T>]) -> MemArray<Idx, T>
+where Idx: ArrayIndex, T: ZeroBits + 'static,
+{
+  let size = concat_size("concatenate", axis, views);
+  let mut dst = MemArray::zeros(Idx::from_nd(size));
+  concatenate_into(axis, views, &mut dst.as_view_mut());
+  dst
+}
+
+/// Stacks `views`, which must all have the same size, along a new axis
+/// before `axis`, into a new packed array.
+pub fn stack<Idx, T>(axis: usize, views: &[MemArrayView<Idx, T>]) -> MemArray<Idx::Higher, T>
+where Idx: InsertAxis, T: ZeroBits + 'static,
+{
+  assert!(!views.is_empty(), "stack: no inputs");
+  let nd = views[0].size.to_nd().len();
+  assert!(axis <= nd, "stack: axis out of bounds: axis: {} ndim: {}", axis, nd);
+  for (i, view) in views.iter().enumerate().skip(1) {
+    assert!(view.size == views[0].size,
+        "stack: size mismatch: input {}: size: {:?} input 0 size: {:?}", i, view.size, views[0].size);
+  }
+  let views: Vec<_> = views.iter().map(|view| view.clone().insert_axis(axis)).collect();
+  concatenate(axis, &views)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ::{MemArray0d, MemArray2d, MemArray3d, MemArrayViewMut2d, SliceSpec};
+  use arrayidx::{Index0d, Index1d};
+
+  // Adds `x` to every element, so that overlapping writes are visible as
+  // sums in the parent array.
+  fn add_all(view: &mut MemArrayViewMut2d<f32>, x: f32) {
+    let size = view.size;
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        let y = view[[i, j]];
+        view.set([i, j], y + x);
+      }
+    }
+  }
+
+  #[test]
+  fn split_at_mut_disjoint() {
+    for &axis in [0, 1].iter() {
+      for i in 0 ..= 4 {
+        let mut a = MemArray2d::<f32>::zeros([4, 4]);
+        {
+          let (mut lo, mut hi) = a.as_view_mut().split_at_mut(axis, i);
+          assert_eq!(lo.size[axis], i);
+          assert_eq!(hi.size[axis], 4 - i);
+          add_all(&mut lo, 1.0);
+          add_all(&mut hi, 10.0);
+        }
+        for j in 0 .. 4 {
+          for k in 0 .. 4 {
+            let pos = if axis == 0 { k } else { j };
+            let expected = if pos < i { 1.0 } else { 10.0 };
+            assert_eq!(a[[k, j]], expected, "axis: {} i: {} at: {:?}", axis, i, [k, j]);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "split_at_mut: index out of bounds")]
+  fn split_at_mut_out_of_bounds() {
+    let mut a = MemArray2d::<f32>::zeros([4, 3]);
+    let _ = a.as_view_mut().split_at_mut(1, 4);
+  }
+
+  #[test]
+  fn chunks_mut_cover_once() {
+    let mut a = MemArray2d::<f32>::zeros([3, 7]);
+    let chunks: Vec<_> = a.as_view_mut().chunks_mut(1, 3).collect();
+    assert_eq!(chunks.iter().map(|c| c.size[1]).collect::<Vec<_>>(), vec![3, 3, 1]);
+    for (n, mut c) in chunks.into_iter().enumerate() {
+      add_all(&mut c, (n + 1) as f32);
+    }
+    for j in 0 .. 7 {
+      for i in 0 .. 3 {
+        assert_eq!(a[[i, j]], (j / 3 + 1) as f32);
+      }
+    }
+  }
+
+  #[test]
+  fn axis_iter_mut_cover_once() {
+    let mut a = MemArray2d::<f32>::zeros([3, 2]);
+    let rows: Vec<_> = a.as_view_mut().axis_iter_mut(0).collect();
+    assert_eq!(rows.len(), 3);
+    for (n, mut r) in rows.into_iter().enumerate() {
+      for j in 0 .. 2 {
+        let y = r[j];
+        r.set(j, y + n as f32);
+      }
+    }
+    for j in 0 .. 2 {
+      for i in 0 .. 3 {
+        assert_eq!(a[[i, j]], i as f32);
+      }
+    }
+  }
+
+  fn iota(size: [usize; 2], base: f32) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], base + (i + 10 * j) as f32);
+      }
+    }
+    a
+  }
+
+  #[test]
+  fn concatenate_axes() {
+    let a = iota([2, 3], 0.0);
+    let b = iota([1, 3], 100.0);
+    let c = concatenate(0, &[a.as_view(), b.as_view()]);
+    assert_eq!(c.size, [3, 3]);
+    assert_eq!(c[[1, 2]], 21.0);
+    assert_eq!(c[[2, 0]], 100.0);
+    assert_eq!(c[[2, 2]], 120.0);
+    let b = iota([2, 2], 100.0);
+    let c = concatenate(1, &[a.as_view(), b.as_view()]);
+    assert_eq!(c.size, [2, 5]);
+    assert_eq!(c.as_view().flat_slice().unwrap(),
+        &[0.0, 1.0, 10.0, 11.0, 20.0, 21.0, 100.0, 101.0, 110.0, 111.0]);
+  }
+
+  #[test]
+  fn concatenate_strided() {
+    let a = iota([4, 3], 0.0);
+    let rows = a.as_view().view((1 .. 3, ..));
+    let rev = a.as_view().slice::<Index2d>(&[SliceSpec::from(..1), SliceSpec::all().rev()]);
+    let c = concatenate(0, &[rows, rev]);
+    assert_eq!(c.size, [3, 3]);
+    assert_eq!(c[[0, 0]], 1.0);
+    assert_eq!(c[[1, 2]], 22.0);
+    assert_eq!(c[[2, 0]], 20.0);
+    assert_eq!(c[[2, 2]], 0.0);
+    let mut big = MemArray2d::<f32>::zeros([5, 3]);
+    {
+      let mut dst = big.view_mut((1 .. 4, ..));
+      concatenate_into(0, &[a.as_view().view((.. 1, ..)), a.as_view().view((2 .., ..))], &mut dst);
+    }
+    assert_eq!(big[[0, 1]], 0.0);
+    assert_eq!(big[[1, 1]], 10.0);
+    assert_eq!(big[[2, 1]], 12.0);
+    assert_eq!(big[[3, 1]], 13.0);
+    assert_eq!(big[[4, 1]], 0.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "concatenate: size mismatch: input 1")]
+  fn concatenate_size_mismatch() {
+    let a = iota([2, 3], 0.0);
+    let b = iota([2, 2], 0.0);
+    concatenate(0, &[a.as_view(), b.as_view()]);
+  }
+
+  #[test]
+  fn stack_axes() {
+    let a = iota([2, 3], 0.0);
+    let b = iota([2, 3], 100.0);
+    let views = [a.as_view(), b.as_view()];
+    let s0 = stack(0, &views);
+    assert_eq!(s0.size, [2, 2, 3]);
+    assert_eq!(s0[[1, 1, 2]], 121.0);
+    let s1 = stack(1, &views);
+    assert_eq!(s1.size, [2, 2, 3]);
+    assert_eq!(s1[[1, 0, 2]], 21.0);
+    assert_eq!(s1[[0, 1, 2]], 120.0);
+    let s2 = stack(2, &views);
+    assert_eq!(s2.size, [2, 3, 2]);
+    assert_eq!(s2[[1, 2, 0]], 21.0);
+    assert_eq!(s2[[1, 2, 1]], 121.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "stack: size mismatch: input 1")]
+  fn stack_size_mismatch() {
+    let a = iota([2, 3], 0.0);
+    let b = iota([3, 2], 0.0);
+    stack(0, &[a.as_view(), b.as_view()]);
+  }
+
+  #[test]
+  fn split_round_trip() {
+    let a = iota([3, 5], 0.0);
+    let parts = a.split(1, &[2, 0, 3]);
+    assert_eq!(parts.iter().map(|p| p.size).collect::<Vec<_>>(), vec![[3, 2], [3, 0], [3, 3]]);
+    assert_eq!(parts[2][[1, 0]], 21.0);
+    let b = concatenate(1, &parts);
+    assert_eq!(b.as_view().flat_slice(), a.as_view().flat_slice());
+  }
+
+  #[test]
+  #[should_panic(expected = "split: size mismatch")]
+  fn split_size_mismatch() {
+    let a = iota([3, 5], 0.0);
+    a.split(1, &[2, 2]);
+  }
+
+  fn iota3(size: [usize; 3]) -> MemArray3d<f32> {
+    let mut a = MemArray3d::zeros(size);
+    for k in 0 .. size[2] {
+      for j in 0 .. size[1] {
+        for i in 0 .. size[0] {
+          a.set([i, j, k], (i + 10 * j + 100 * k) as f32);
+        }
+      }
+    }
+    a
+  }
+
+  #[test]
+  fn index_axis_each_axis() {
+    let a = iota3([2, 3, 4]);
+    let v = a.index_axis(0, 1);
+    assert_eq!(v.size, [3, 4]);
+    assert_eq!(v[[2, 3]], 321.0);
+    let v = a.index_axis(1, 2);
+    assert_eq!(v.size, [2, 4]);
+    assert_eq!(v[[1, 3]], 321.0);
+    let v = a.index_axis(2, 3);
+    assert_eq!(v.size, [2, 3]);
+    assert_eq!(v.iter().cloned().collect::<Vec<_>>(), vec![300.0, 301.0, 310.0, 311.0, 320.0, 321.0]);
+    let mut b = iota3([2, 3, 4]);
+    b.index_axis_mut(1, 0).set([1, 2], -1.0);
+    assert_eq!(b[[1, 0, 2]], -1.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "index_axis: index out of bounds: index: 3 axis: 1 len: 3")]
+  fn index_axis_out_of_bounds() {
+    let a = iota3([2, 3, 4]);
+    a.index_axis(1, 3);
+  }
+
+  #[test]
+  fn insert_axis_first_and_last() {
+    let a = iota3([2, 3, 1]).as_view().squeeze::<[usize; 2]>().to_packed();
+    let v = a.insert_axis(0);
+    assert_eq!(v.size, [1, 2, 3]);
+    assert_eq!(v[[0, 1, 2]], 21.0);
+    let v = a.insert_axis(2);
+    assert_eq!(v.size, [2, 3, 1]);
+    assert_eq!(v[[1, 2, 0]], 21.0);
+    assert_eq!(v.iter().cloned().collect::<Vec<_>>(), a.as_view().iter().cloned().collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn squeeze_ones() {
+    let a = iota3([1, 3, 1]);
+    let v = a.squeeze::<Index1d>();
+    assert_eq!(v.size, 3);
+    assert_eq!(v[2], 20.0);
+    let mut b = MemArray3d::<f32>::zeros([1, 1, 1]);
+    b.set([0, 0, 0], 5.0);
+    let v = b.squeeze::<Index0d>();
+    assert_eq!(v.size, ());
+    assert_eq!(v[()], 5.0);
+    b.squeeze_mut::<Index0d>().set((), 6.0);
+    assert_eq!(b[[0, 0, 0]], 6.0);
+    let c = MemArray0d::<f32>::zeros(());
+    assert_eq!(c.insert_axis(0).squeeze::<Index0d>().size, ());
+  }
+
+  #[test]
+  #[should_panic(expected = "squeeze: rank mismatch")]
+  fn squeeze_rank_mismatch() {
+    let a = iota3([1, 3, 2]);
+    a.squeeze::<Index1d>();
+  }
+}
@@ -0,0 +1,174 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{Mem, MemArray, MemArrayView, MemArrayViewMut, for_each_flat_offset_n, packed_prefix};
+
+use arrayidx::*;
+
+use std::ptr::{copy_nonoverlapping};
+
+/// Elementwise conversion for `cast_from`, with the semantics of `as`.
+pub trait CastFrom<U> {
+  fn cast_from(u: U) -> Self;
+}
+
+macro_rules! impl_cast_from {
+  ($($t:ty),*) => {
+    impl_cast_from!(@each [$($t),*] $($t),*);
+  };
+  (@each $us:tt $($t:ty),*) => {
+    $( impl_cast_from!(@one $t, $us); )*
+  };
+  (@one $t:ty, [$($u:ty),*]) => {
+    $(
+      impl CastFrom<$u> for $t {
+        fn cast_from(u: $u) -> $t {
+          u as $t
+        }
+      }
+    )*
+  };
+}
+
+impl_cast_from!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  /// Copies `src`, which must have the same size, into this view. Runs of
+  /// elements which are contiguous in both views are copied with `memcpy`.
+  pub fn copy_from(&mut self, src: &MemArrayView<Idx, T>) {
+    assert_eq!(self.size, src.size,
+        "MemArrayViewMut::copy_from: size mismatch: dst size: {:?} src size: {:?}", self.size, src.size);
+    let size = self.size.to_nd();
+    let (dst_stride, src_stride) = (self.stride.to_nd(), src.stride.to_nd());
+    let (k, run) = packed_prefix(&size, &[&dst_stride, &src_stride]);
+    let src_mem = src.mem.as_slice();
+    let (dst_ptr, dst_len) = (self.ptr, self.mem_len);
+    for_each_flat_offset_n(&size[k ..], &[&dst_stride[k ..], &src_stride[k ..]], &[self.offset, src.offset], |off| {
+      assert!(off[0] + run <= dst_len);
+      let src_run = &src_mem[off[1] .. off[1] + run];
+      unsafe { copy_nonoverlapping(src_run.as_ptr(), dst_ptr.offset(off[0] as isize), run) };
+    });
+  }
+
+  /// Like `copy_from`, but converts each element of `src` with `CastFrom`.
+  pub fn cast_from<U>(&mut self, src: &MemArrayView<Idx, U>) where U: Copy + 'static, T: CastFrom<U> {
+    assert_eq!(self.size, src.size,
+        "MemArrayViewMut::cast_from: size mismatch: dst size: {:?} src size: {:?}", self.size, src.size);
+    let size = self.size.to_nd();
+    let (dst_stride, src_stride) = (self.stride.to_nd(), src.stride.to_nd());
+    let src_mem = src.mem.as_slice();
+    let (dst_ptr, dst_len) = (self.ptr, self.mem_len);
+    for_each_flat_offset_n(&size, &[&dst_stride, &src_stride], &[self.offset, src.offset], |off| {
+      assert!(off[0] < dst_len);
+      unsafe { *dst_ptr.offset(off[0] as isize) = T::cast_from(src_mem[off[1]]) };
+    });
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy + 'static, M: Mem<T> {
+  pub fn copy_from(&mut self, src: &MemArrayView<Idx, T>) {
+    self.as_view_mut().copy_from(src);
+  }
+
+  pub fn cast_from<U>(&mut self, src: &MemArrayView<Idx, U>) where U: Copy + 'static, T: CastFrom<U> {
+    self.as_view_mut().cast_from(src);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ::{DenseArray, MemArray1d, MemArray2d, SliceSpec};
+
+  use arrayidx::{Index2d};
+
+  fn iota(size: [usize; 2]) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
+    }
+    a
+  }
+
+  #[test]
+  fn copy_packed() {
+    let a = iota([3, 2]);
+    let mut b = MemArray2d::<f32>::zeros([3, 2]);
+    b.copy_from(&a.as_view());
+    assert_eq!(b.as_view().iter().cloned().collect::<Vec<_>>(), a.as_view().iter().cloned().collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn copy_strided() {
+    let a = iota([4, 3]);
+    // Strided source into a packed destination.
+    let mut b = MemArray2d::<f32>::zeros([2, 3]);
+    b.copy_from(&a.as_view().view((1 .. 3, ..)));
+    assert_eq!(b.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0, 2.0, 11.0, 12.0, 21.0, 22.0]);
+    // Packed source into a strided destination; the rest is left alone.
+    let mut c = MemArray2d::<f32>::zeros([4, 3]);
+    {
+      let mut v = c.as_view_mut().view_mut((2 .., 1 ..));
+      assert!(!v.is_packed());
+      v.copy_from(&iota([2, 2]).as_view());
+    }
+    assert_eq!(c.as_view().iter().cloned().collect::<Vec<_>>(),
+        vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 10.0, 11.0]);
+    // Reversed and transposed sources are copied by index.
+    let mut d = MemArray2d::<f32>::zeros([4, 3]);
+    d.copy_from(&a.as_view().slice::<Index2d>(&[SliceSpec::all().rev(), SliceSpec::all()]));
+    assert_eq!(d[[0, 2]], 23.0);
+    assert_eq!(d[[3, 0]], 0.0);
+    let mut e = MemArray2d::<f32>::zeros([3, 4]);
+    e.copy_from(&a.as_view().transpose());
+    assert_eq!(e[[2, 1]], 21.0);
+  }
+
+  #[test]
+  fn cast_truncates_and_wraps() {
+    let mut a = MemArray1d::<f32>::zeros(4);
+    a.set(0, 1.9);
+    a.set(1, -1.9);
+    a.set(2, 2.5);
+    a.set(3, -0.5);
+    let mut b = MemArray1d::<i32>::zeros(4);
+    b.cast_from(&a.as_view());
+    assert_eq!(b.as_view().iter().cloned().collect::<Vec<_>>(), vec![1, -1, 2, 0]);
+    let mut c = MemArray1d::<i32>::zeros(3);
+    c.set(0, 256);
+    c.set(1, 257);
+    c.set(2, -1);
+    let mut d = MemArray1d::<u8>::zeros(3);
+    d.cast_from(&c.as_view());
+    assert_eq!(d.as_view().iter().cloned().collect::<Vec<_>>(), vec![0, 1, 255]);
+  }
+
+  #[test]
+  fn cast_strided() {
+    let a = iota([3, 2]);
+    let mut b = MemArray2d::<u8>::zeros([2, 2]);
+    b.cast_from(&a.as_view().view((1 .., ..)));
+    assert_eq!(b.as_view().iter().cloned().collect::<Vec<_>>(), vec![1, 2, 11, 12]);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayViewMut::copy_from: size mismatch")]
+  fn copy_mismatch() {
+    let mut b = MemArray2d::<f32>::zeros([2, 3]);
+    b.copy_from(&iota([3, 2]).as_view());
+  }
+}
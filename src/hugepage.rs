@@ -0,0 +1,245 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{Mem, MemArray, ReadOnlyMem, ZeroBits};
+use accounting::{MemAccount, TagMem};
+
+use arrayidx::{ArrayIndex};
+use libc;
+
+use std::fs::{read_to_string};
+use std::mem::{size_of};
+use std::ptr::{null_mut};
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+
+const HUGE_PAGE_SZ: usize = 2 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HugePageBacking {
+  /// Explicit huge pages from the hugetlbfs pool (`MAP_HUGETLB`).
+  Explicit,
+  /// Regular pages advised with `madvise(MADV_HUGEPAGE)` while transparent
+  /// huge pages are enabled. This is only a hint: the kernel promotes the
+  /// pages on a best-effort basis, and may back some or all of them with
+  /// regular pages.
+  TransparentAdvised,
+  /// Regular pages; neither kind of huge page was available.
+  Regular,
+}
+
+/// Anonymous memory mapping which prefers huge pages, to cut down on TLB
+/// misses for very large arrays.
+pub struct HugePageMem<T> where T: Copy {
+  buf:      *mut T,
+  len:      usize,
+  map_len:  usize,
+  backing:  HugePageBacking,
+  acct:     MemAccount,
+}
+
+unsafe impl<T> Send for HugePageMem<T> where T: Copy + Send {}
+unsafe impl<T> Sync for HugePageMem<T> where T: Copy + Sync {}
+
+impl<T> Drop for HugePageMem<T> where T: Copy {
+  fn drop(&mut self) {
+    assert!(!self.buf.is_null());
+    unsafe { libc::munmap(self.buf as *mut libc::c_void, self.map_len) };
+    self.buf = null_mut();
+  }
+}
+
+unsafe fn map_anon(map_len: usize, extra_flags: libc::c_int) -> Option<*mut u8> {
+  let p = libc::mmap(
+      null_mut(),
+      map_len,
+      libc::PROT_READ | libc::PROT_WRITE,
+      libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | extra_flags,
+      -1,
+      0,
+  );
+  if p == libc::MAP_FAILED {
+    return None;
+  }
+  Some(p as *mut u8)
+}
+
+// Over-maps by one huge page and trims the ends, so that the mapping starts
+// on a huge page boundary and can be backed entirely by huge pages.
+unsafe fn map_anon_aligned(map_len: usize) -> Option<*mut u8> {
+  let raw = map_anon(map_len.checked_add(HUGE_PAGE_SZ)?, 0)?;
+  let addr = raw as usize;
+  let aligned = (addr + HUGE_PAGE_SZ - 1) & !(HUGE_PAGE_SZ - 1);
+  let head = aligned - addr;
+  let tail = HUGE_PAGE_SZ - head;
+  if head > 0 {
+    libc::munmap(raw as *mut libc::c_void, head);
+  }
+  if tail > 0 {
+    libc::munmap((aligned + map_len) as *mut libc::c_void, tail);
+  }
+  Some(aligned as *mut u8)
+}
+
+const THP_ENABLED_PATH: &'static str = "/sys/kernel/mm/transparent_hugepage/enabled";
+
+// The sysfs file lists the modes with the active one in brackets, e.g.
+// "always [madvise] never". `MADV_HUGEPAGE` has no effect in "never" mode.
+fn parse_thp_enabled(s: &str) -> bool {
+  match s.split_whitespace().find(|tok| tok.starts_with('[')) {
+    Some("[always]") | Some("[madvise]") => true,
+    _ => false,
+  }
+}
+
+fn thp_enabled() -> bool {
+  match read_to_string(THP_ENABLED_PATH) {
+    Err(_) => false,
+    Ok(s) => parse_thp_enabled(&s),
+  }
+}
+
+impl<T> HugePageMem<T> where T: ZeroBits {
+  /// Allocates zeroed memory, trying explicit huge pages first, then
+  /// transparent huge pages, then falling back to regular pages. Use
+  /// `backing` to find out which one was obtained; note that
+  /// `TransparentAdvised` does not guarantee huge pages.
+  pub fn zeros(len: usize) -> Self {
+    // Also leaves room for the extra huge page over-mapped by
+    // `map_anon_aligned`.
+    let map_len = match len.checked_mul(size_of::<T>())
+      .and_then(|phsz| phsz.max(1).checked_add(HUGE_PAGE_SZ - 1))
+      .map(|n| n / HUGE_PAGE_SZ * HUGE_PAGE_SZ)
+      .filter(|n| n.checked_add(HUGE_PAGE_SZ).is_some())
+    {
+      None => panic!("HugePageMem: allocation too large: len: {}", len),
+      Some(map_len) => map_len,
+    };
+    // Anonymous mappings are zero-filled by the kernel.
+    let (p, backing) = unsafe {
+      match map_anon(map_len, libc::MAP_HUGETLB) {
+        Some(p) => (p, HugePageBacking::Explicit),
+        None => {
+          let p = match map_anon_aligned(map_len) {
+            None => panic!("HugePageMem: mmap failed: len: {}", len),
+            Some(p) => p,
+          };
+          if thp_enabled() &&
+              libc::madvise(p as *mut libc::c_void, map_len, libc::MADV_HUGEPAGE) == 0
+          {
+            (p, HugePageBacking::TransparentAdvised)
+          } else {
+            (p, HugePageBacking::Regular)
+          }
+        }
+      }
+    };
+    HugePageMem{
+      buf:      p as *mut T,
+      len:      len,
+      map_len:  map_len,
+      backing:  backing,
+      acct:     MemAccount::new(map_len),
+    }
+  }
+}
+
+impl<T> HugePageMem<T> where T: Copy {
+  pub fn backing(&self) -> HugePageBacking {
+    self.backing
+  }
+}
+
+impl<T> TagMem for HugePageMem<T> where T: Copy {
+  fn tag(&self) -> &'static str {
+    self.acct.tag()
+  }
+
+  fn set_tag(&mut self, tag: &'static str) {
+    self.acct.set_tag(tag);
+  }
+}
+
+impl<T> ReadOnlyMem<T> for HugePageMem<T> where T: Copy {
+  unsafe fn as_ptr(&self) -> *const T {
+    self.buf
+  }
+
+  fn as_slice(&self) -> &[T] {
+    unsafe { from_raw_parts(self.buf, self.len) }
+  }
+
+  fn as_bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts(self.buf as *const u8, self.len * size_of::<T>()) }
+  }
+}
+
+impl<T> Mem<T> for HugePageMem<T> where T: Copy {
+  unsafe fn as_mut_ptr(&mut self) -> *mut T {
+    self.buf
+  }
+
+  fn as_mut_slice(&mut self) -> &mut [T] {
+    unsafe { from_raw_parts_mut(self.buf, self.len) }
+  }
+
+  fn as_mut_bytes(&mut self) -> &mut [u8] {
+    unsafe { from_raw_parts_mut(self.buf as *mut u8, self.len * size_of::<T>()) }
+  }
+}
+
+impl<Idx, T> MemArray<Idx, T, HugePageMem<T>> where Idx: ArrayIndex, T: ZeroBits {
+  pub fn zeros_huge(size: Idx) -> Self {
+    let mem = HugePageMem::<T>::zeros(size.flat_len());
+    MemArray::with_memory(size, mem)
+  }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn thp_enabled_modes() {
+    assert!(parse_thp_enabled("[always] madvise never\n"));
+    assert!(parse_thp_enabled("always [madvise] never\n"));
+    assert!(!parse_thp_enabled("always madvise [never]\n"));
+    assert!(!parse_thp_enabled(""));
+  }
+
+  #[test]
+  fn zeros_huge() {
+    let mut mem = HugePageMem::<u64>::zeros(HUGE_PAGE_SZ / 8 + 1);
+    if !thp_enabled() {
+      assert!(mem.backing() != HugePageBacking::TransparentAdvised);
+    }
+    assert!(mem.as_slice().iter().all(|&x| x == 0));
+    let n = mem.as_slice().len();
+    mem.as_mut_slice()[n - 1] = 7;
+    assert_eq!(mem.as_slice()[n - 1], 7);
+  }
+
+  #[test]
+  #[should_panic(expected = "HugePageMem: allocation too large: len: 2305843009213693952")]
+  fn zeros_too_large() {
+    HugePageMem::<u64>::zeros(usize::max_value() / 8 + 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "HugePageMem: allocation too large")]
+  fn zeros_too_large_to_round_up() {
+    HugePageMem::<u8>::zeros(usize::max_value() - HUGE_PAGE_SZ);
+  }
+}
@@ -71,6 +71,69 @@ impl ToNpyDtypeDesc for u8 {
   }
 }
 
+impl ToNpyDtypeDesc for u16 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   Some(NpyEndianness::native()),
+      dtype:    NpyDtype::UInt16,
+    }
+  }
+}
+
+impl ToNpyDtypeDesc for u32 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   Some(NpyEndianness::native()),
+      dtype:    NpyDtype::UInt32,
+    }
+  }
+}
+
+impl ToNpyDtypeDesc for u64 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   Some(NpyEndianness::native()),
+      dtype:    NpyDtype::UInt64,
+    }
+  }
+}
+
+impl ToNpyDtypeDesc for i8 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   None,
+      dtype:    NpyDtype::Int8,
+    }
+  }
+}
+
+impl ToNpyDtypeDesc for i16 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   Some(NpyEndianness::native()),
+      dtype:    NpyDtype::Int16,
+    }
+  }
+}
+
+impl ToNpyDtypeDesc for i32 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   Some(NpyEndianness::native()),
+      dtype:    NpyDtype::Int32,
+    }
+  }
+}
+
+impl ToNpyDtypeDesc for i64 {
+  fn to_npy_dtype_desc() -> NpyDtypeDesc {
+    NpyDtypeDesc{
+      endian:   Some(NpyEndianness::native()),
+      dtype:    NpyDtype::Int64,
+    }
+  }
+}
+
 impl ToNpyDtypeDesc for f32 {
   fn to_npy_dtype_desc() -> NpyDtypeDesc {
     NpyDtypeDesc{
@@ -99,6 +162,13 @@ impl NpyDtypeDesc {
   pub fn parse(desc: &str) -> Result<Self, ()> {
     let (endian, dtype) = match desc {
       "'|u1'," => (None,                        NpyDtype::UInt8),
+      "'<u2'," => (Some(NpyEndianness::Little), NpyDtype::UInt16),
+      "'<u4'," => (Some(NpyEndianness::Little), NpyDtype::UInt32),
+      "'<u8'," => (Some(NpyEndianness::Little), NpyDtype::UInt64),
+      "'|i1'," => (None,                        NpyDtype::Int8),
+      "'<i2'," => (Some(NpyEndianness::Little), NpyDtype::Int16),
+      "'<i4'," => (Some(NpyEndianness::Little), NpyDtype::Int32),
+      "'<i8'," => (Some(NpyEndianness::Little), NpyDtype::Int64),
       "'<f4'," => (Some(NpyEndianness::Little), NpyDtype::Float32),
       "'<f8'," => (Some(NpyEndianness::Little), NpyDtype::Float64),
       _ => unimplemented!("NpyDtypeDesc: unhandled str: {}", desc),
@@ -0,0 +1,362 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{DenseArray, MemArrayView, MemArrayViewMut};
+
+use arrayidx::{ArrayIndex};
+
+use std::marker::{PhantomData};
+use std::slice::{self, from_raw_parts, from_raw_parts_mut};
+
+// Walks the flat offsets of a strided array in column-major order (axis 0
+// fastest), keeping track of the current index. Negative strides are stored
+// in two's complement, as everywhere else.
+#[derive(Clone)]
+struct StridedCursor {
+  size:     Vec<usize>,
+  stride:   Vec<usize>,
+  idx:      Vec<usize>,
+  off:      usize,
+  rem:      usize,
+}
+
+impl StridedCursor {
+  fn new(size: Vec<usize>, stride: Vec<usize>, offset: usize) -> Self {
+    let rem = size.iter().product();
+    let idx = vec![0; size.len()];
+    StridedCursor{
+      size:     size,
+      stride:   stride,
+      idx:      idx,
+      off:      offset,
+      rem:      rem,
+    }
+  }
+
+  fn advance(&mut self) {
+    for d in 0 .. self.size.len() {
+      self.idx[d] += 1;
+      self.off = self.off.wrapping_add(self.stride[d]);
+      if self.idx[d] < self.size[d] {
+        return;
+      }
+      self.off = self.off.wrapping_sub(self.stride[d].wrapping_mul(self.size[d]));
+      self.idx[d] = 0;
+    }
+  }
+
+  fn next(&mut self) -> Option<usize> {
+    if self.rem == 0 {
+      return None;
+    }
+    let off = self.off;
+    self.rem -= 1;
+    if self.rem > 0 {
+      self.advance();
+    }
+    Some(off)
+  }
+
+  fn next_indexed(&mut self) -> Option<(Vec<usize>, usize)> {
+    if self.rem == 0 {
+      return None;
+    }
+    let idx = self.idx.clone();
+    self.next().map(|off| (idx, off))
+  }
+}
+
+enum IterKind<'a, T> where T: 'a {
+  Packed(slice::Iter<'a, T>),
+  Strided(*const T, usize, StridedCursor),
+}
+
+/// Iterator over the elements of a view in column-major index order (axis 0
+/// fastest). This is memory order only for packed views; transposed and
+/// reversed views are still visited by index, so that iterating two views of
+/// the same size together pairs up elements at the same index.
+pub struct Iter<'a, T> where T: 'a {
+  kind:     IterKind<'a, T>,
+  _mrk:     PhantomData<&'a T>,
+}
+
+unsafe impl<'a, T> Send for Iter<'a, T> where T: Sync {}
+unsafe impl<'a, T> Sync for Iter<'a, T> where T: Sync {}
+
+impl<'a, T> Iter<'a, T> where T: 'a {
+  // The memory is passed as a pointer and length rather than a slice, since
+  // mutable views split from one another share their memory.
+  unsafe fn new(ptr: *const T, mem_len: usize, size: Vec<usize>, stride: Vec<usize>, offset: usize, packed: bool) -> Self {
+    let kind = if packed {
+      let len = size.iter().product::<usize>();
+      assert!(offset + len <= mem_len);
+      IterKind::Packed(from_raw_parts(ptr.offset(offset as isize), len).iter())
+    } else {
+      IterKind::Strided(ptr, mem_len, StridedCursor::new(size, stride, offset))
+    };
+    Iter{kind, _mrk: PhantomData}
+  }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> where T: 'a {
+  type Item = &'a T;
+
+  fn next(&mut self) -> Option<&'a T> {
+    match self.kind {
+      IterKind::Packed(ref mut iter) => iter.next(),
+      IterKind::Strided(ptr, len, ref mut cursor) => cursor.next().map(|off| {
+        assert!(off < len);
+        unsafe { &*ptr.offset(off as isize) }
+      }),
+    }
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.len();
+    (len, Some(len))
+  }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> where T: 'a {
+  fn len(&self) -> usize {
+    match self.kind {
+      IterKind::Packed(ref iter) => iter.len(),
+      IterKind::Strided(_, _, ref cursor) => cursor.rem,
+    }
+  }
+}
+
+enum IterMutKind<'a, T> where T: 'a {
+  Packed(slice::IterMut<'a, T>),
+  Strided(*mut T, usize, StridedCursor),
+}
+
+/// Mutable iterator over the elements of a view, in the same order as
+/// `Iter`.
+pub struct IterMut<'a, T> where T: 'a {
+  kind:     IterMutKind<'a, T>,
+  _mrk:     PhantomData<&'a mut T>,
+}
+
+unsafe impl<'a, T> Send for IterMut<'a, T> where T: Send {}
+unsafe impl<'a, T> Sync for IterMut<'a, T> where T: Sync {}
+
+impl<'a, T> IterMut<'a, T> where T: 'a {
+  unsafe fn new(ptr: *mut T, mem_len: usize, size: Vec<usize>, stride: Vec<usize>, offset: usize, packed: bool) -> Self {
+    let kind = if packed {
+      let len = size.iter().product::<usize>();
+      assert!(offset + len <= mem_len);
+      IterMutKind::Packed(from_raw_parts_mut(ptr.offset(offset as isize), len).iter_mut())
+    } else {
+      IterMutKind::Strided(ptr, mem_len, StridedCursor::new(size, stride, offset))
+    };
+    IterMut{kind, _mrk: PhantomData}
+  }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> where T: 'a {
+  type Item = &'a mut T;
+
+  fn next(&mut self) -> Option<&'a mut T> {
+    match self.kind {
+      IterMutKind::Packed(ref mut iter) => iter.next(),
+      IterMutKind::Strided(ptr, len, ref mut cursor) => cursor.next().map(|off| {
+        assert!(off < len);
+        // Distinct indices of a (non-broadcast) view map to distinct
+        // offsets, so no element is handed out twice.
+        unsafe { &mut *ptr.offset(off as isize) }
+      }),
+    }
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.len();
+    (len, Some(len))
+  }
+}
+
+impl<'a, T> ExactSizeIterator for IterMut<'a, T> where T: 'a {
+  fn len(&self) -> usize {
+    match self.kind {
+      IterMutKind::Packed(ref iter) => iter.len(),
+      IterMutKind::Strided(_, _, ref cursor) => cursor.rem,
+    }
+  }
+}
+
+/// Iterator over the elements of a view together with their indices, in
+/// the same order as `Iter`.
+pub struct IndexedIter<'a, Idx, T> where T: 'a {
+  ptr:      *const T,
+  mem_len:  usize,
+  cursor:   StridedCursor,
+  _mrk:     PhantomData<(Idx, &'a T)>,
+}
+
+unsafe impl<'a, Idx, T> Send for IndexedIter<'a, Idx, T> where Idx: Send, T: Sync {}
+unsafe impl<'a, Idx, T> Sync for IndexedIter<'a, Idx, T> where Idx: Sync, T: Sync {}
+
+impl<'a, Idx, T> Iterator for IndexedIter<'a, Idx, T> where Idx: ArrayIndex, T: 'a {
+  type Item = (Idx, &'a T);
+
+  fn next(&mut self) -> Option<(Idx, &'a T)> {
+    let (ptr, len) = (self.ptr, self.mem_len);
+    self.cursor.next_indexed().map(|(idx, off)| {
+      assert!(off < len);
+      (Idx::from_nd(idx), unsafe { &*ptr.offset(off as isize) })
+    })
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (self.cursor.rem, Some(self.cursor.rem))
+  }
+}
+
+impl<'a, Idx, T> ExactSizeIterator for IndexedIter<'a, Idx, T> where Idx: ArrayIndex, T: 'a {
+}
+
+impl<'a, Idx, T> MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  pub fn iter(&self) -> Iter<'a, T> {
+    let packed = self.is_packed();
+    let mem: &'a [T] = self.mem.as_slice();
+    unsafe { Iter::new(mem.as_ptr(), mem.len(), self.size.to_nd(), self.stride.to_nd(), self.offset, packed) }
+  }
+
+  pub fn indexed_iter(&self) -> IndexedIter<'a, Idx, T> {
+    let mem: &'a [T] = self.mem.as_slice();
+    IndexedIter{
+      ptr:      mem.as_ptr(),
+      mem_len:  mem.len(),
+      cursor:   StridedCursor::new(self.size.to_nd(), self.stride.to_nd(), self.offset),
+      _mrk:     PhantomData,
+    }
+  }
+}
+
+impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  pub fn iter<'b>(&'b self) -> Iter<'b, T> {
+    let packed = self.is_packed();
+    unsafe { Iter::new(self.ptr, self.mem_len, self.size.to_nd(), self.stride.to_nd(), self.offset, packed) }
+  }
+
+  pub fn iter_mut<'b>(&'b mut self) -> IterMut<'b, T> {
+    let packed = self.is_packed();
+    unsafe { IterMut::new(self.ptr, self.mem_len, self.size.to_nd(), self.stride.to_nd(), self.offset, packed) }
+  }
+
+  /// Like `iter_mut`, but consumes the view, so that the iterator can
+  /// outlive it.
+  pub fn into_iter_mut(self) -> IterMut<'a, T> {
+    let packed = self.is_packed();
+    unsafe { IterMut::new(self.ptr, self.mem_len, self.size.to_nd(), self.stride.to_nd(), self.offset, packed) }
+  }
+
+  pub fn indexed_iter<'b>(&'b self) -> IndexedIter<'b, Idx, T> {
+    IndexedIter{
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      cursor:   StridedCursor::new(self.size.to_nd(), self.stride.to_nd(), self.offset),
+      _mrk:     PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ::{DenseArray, MemArray2d, SliceSpec};
+
+  use arrayidx::{Index2d};
+
+  fn iota(size: [usize; 2]) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
+    }
+    a
+  }
+
+  #[test]
+  fn iter_packed_and_strided() {
+    let a = iota([3, 2]);
+    let v = a.as_view();
+    assert!(v.is_packed());
+    assert_eq!(v.iter().cloned().collect::<Vec<_>>(), vec![0.0, 1.0, 2.0, 10.0, 11.0, 12.0]);
+    // The strided path visits the same elements as a packed copy would.
+    let s = a.as_view().view((1 .., ..));
+    assert!(!s.is_packed());
+    let packed = s.to_packed();
+    assert_eq!(s.iter().cloned().collect::<Vec<_>>(), packed.as_view().iter().cloned().collect::<Vec<_>>());
+    assert_eq!(s.iter().cloned().collect::<Vec<_>>(), vec![1.0, 2.0, 11.0, 12.0]);
+  }
+
+  #[test]
+  fn iter_transposed_and_reversed() {
+    let a = iota([3, 2]);
+    let t = a.as_view().transpose();
+    assert_eq!(t.iter().cloned().collect::<Vec<_>>(), vec![0.0, 10.0, 1.0, 11.0, 2.0, 12.0]);
+    let r = a.as_view().slice::<Index2d>(&[SliceSpec::all().rev(), SliceSpec::all()]);
+    assert_eq!(r.iter().cloned().collect::<Vec<_>>(), vec![2.0, 1.0, 0.0, 12.0, 11.0, 10.0]);
+    let idx: Vec<_> = r.indexed_iter().map(|(idx, &x)| (idx, x)).collect();
+    assert_eq!(idx[0], ([0, 0], 2.0));
+    assert_eq!(idx[4], ([1, 1], 11.0));
+    assert!(t.indexed_iter().all(|(idx, &x)| x == t[idx]));
+  }
+
+  #[test]
+  fn iter_size_hint() {
+    let a = iota([3, 4]);
+    let s = a.as_view().view((.. 2, 1 ..));
+    let mut it = s.iter();
+    assert_eq!(it.len(), 6);
+    it.next();
+    assert_eq!(it.size_hint(), (5, Some(5)));
+    assert_eq!(it.count(), 5);
+    let mut it = a.as_view().iter();
+    it.next();
+    assert_eq!(it.len(), 11);
+    let mut it = s.indexed_iter();
+    it.next();
+    it.next();
+    assert_eq!(it.size_hint(), (4, Some(4)));
+    let empty = a.as_view().view((.. 0, ..));
+    assert_eq!(empty.iter().len(), 0);
+    assert!(empty.indexed_iter().next().is_none());
+  }
+
+  #[test]
+  fn iter_mut_strided() {
+    let mut a = iota([3, 2]);
+    {
+      let mut t = a.as_view_mut().transpose();
+      let mut n = 0.0;
+      for x in t.iter_mut() {
+        *x = n;
+        n += 1.0;
+      }
+      assert_eq!(t.iter_mut().len(), 6);
+    }
+    assert_eq!(a.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 2.0, 4.0, 1.0, 3.0, 5.0]);
+    {
+      let v = a.as_view_mut().view_mut((1 .., ..));
+      for x in v.into_iter_mut() {
+        *x = -*x;
+      }
+    }
+    assert_eq!(a.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, -2.0, -4.0, 1.0, -3.0, -5.0]);
+  }
+}
@@ -14,50 +14,542 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-#![feature(allocator_api)]
-#![feature(collections_range)]
-#![feature(ptr_internals)]
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
 
 extern crate arrayidx;
 extern crate byteorder;
 #[cfg(feature = "f16")] extern crate float;
+#[cfg(unix)] extern crate libc;
 extern crate sharedmem;
 
+use accounting::{MemAccount, TagMem};
 use arrayidx::*;
 #[cfg(feature = "f16")] use float::stub::{f16_stub};
 use sharedmem::{SharedMem};
 
-use std::alloc::{Alloc, Global};
-use std::cell::{RefCell};
+use std::alloc::{Layout, handle_alloc_error};
 use std::fmt::{Debug};
 use std::marker::{PhantomData};
-use std::mem::{size_of};
-use std::ops::{RangeBounds};
-use std::ptr::{NonNull, null_mut, write_bytes};
-use std::rc::{Rc};
+use std::mem::{ManuallyDrop, size_of};
+use std::ops::{Bound, Index, IndexMut, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive};
+use std::ptr::{null_mut, read, write_bytes};
 use std::slice::{from_raw_parts, from_raw_parts_mut};
 use std::sync::{Arc};
 
+pub mod accounting;
+pub mod arena;
+pub mod axis;
+pub mod copy;
 pub mod ffi;
+#[cfg(target_os = "linux")] pub mod hugepage;
 pub mod io;
+pub mod iter;
 pub mod linalg;
+pub mod map;
+pub mod ops;
+pub mod pool;
+pub mod reduce;
+#[cfg(unix)] pub mod shm;
 
-fn i2idx(i: isize, len: usize) -> usize {
+fn i2idx(i: isize, len: usize) -> Option<usize> {
   let u = i as usize;
   if u < len {
-    return u;
+    return Some(u);
   }
   let u = (len as isize + i) as usize;
   if u < len {
-    return u;
+    return Some(u);
   }
-  panic!();
+  None
+}
+
+// Resolves a signed range the way numpy resolves a slice with unit step:
+// negative bounds count from the end, and out-of-range bounds are clamped.
+// Bounds are resolved in `isize` and only then clamped, so that e.g. `..=-10`
+// on a short axis is empty.
+fn srange2idx<R>(r: R, len: usize) -> (usize, usize) where R: RangeBounds<isize> {
+  let resolve = |i: isize, inc: isize| -> usize {
+    let i = if i < 0 { i.saturating_add(len as isize) } else { i };
+    let i = i.saturating_add(inc);
+    if i < 0 { 0 } else { (i as usize).min(len) }
+  };
+  let start = match r.start_bound() {
+    Bound::Included(&s) => resolve(s, 0),
+    Bound::Excluded(&s) => resolve(s, 1),
+    Bound::Unbounded => 0,
+  };
+  let end = match r.end_bound() {
+    Bound::Included(&e) => resolve(e, 1),
+    Bound::Excluded(&e) => resolve(e, 0),
+    Bound::Unbounded => len,
+  };
+  (start, end.max(start))
+}
+
+/// An element index which may count from the end of each axis, as in
+/// numpy: `-1` is the last element along an axis.
+pub trait SignedIndex: Copy + Debug {
+  type Idx: ArrayIndex;
+
+  /// Returns `None` if the index is out of bounds for `size`.
+  fn resolve(&self, size: &Self::Idx) -> Option<Self::Idx>;
+}
+
+impl SignedIndex for isize {
+  type Idx = Index1d;
+
+  fn resolve(&self, size: &Index1d) -> Option<Index1d> {
+    i2idx(*self, *size)
+  }
+}
+
+impl SignedIndex for [isize; 2] {
+  type Idx = Index2d;
+
+  fn resolve(&self, size: &Index2d) -> Option<Index2d> {
+    Some([i2idx(self[0], size[0])?, i2idx(self[1], size[1])?])
+  }
+}
+
+impl SignedIndex for [isize; 3] {
+  type Idx = Index3d;
+
+  fn resolve(&self, size: &Index3d) -> Option<Index3d> {
+    Some([i2idx(self[0], size[0])?, i2idx(self[1], size[1])?, i2idx(self[2], size[2])?])
+  }
+}
+
+impl SignedIndex for [isize; 4] {
+  type Idx = Index4d;
+
+  fn resolve(&self, size: &Index4d) -> Option<Index4d> {
+    Some([i2idx(self[0], size[0])?, i2idx(self[1], size[1])?, i2idx(self[2], size[2])?,
+          i2idx(self[3], size[3])?])
+  }
+}
+
+impl SignedIndex for [isize; 5] {
+  type Idx = Index5d;
+
+  fn resolve(&self, size: &Index5d) -> Option<Index5d> {
+    Some([i2idx(self[0], size[0])?, i2idx(self[1], size[1])?, i2idx(self[2], size[2])?,
+          i2idx(self[3], size[3])?, i2idx(self[4], size[4])?])
+  }
+}
+
+/// How to slice one axis of a view; see `MemArrayView::slice`. Ranges are
+/// signed and clamped as in `view_signed`, and convert into `SliceSpec`
+/// with `into()`; a single `isize` selects one element and drops the axis.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SliceSpec {
+  Index(isize),
+  /// Every `step`-th element of `start .. end`, beginning at `start`, and
+  /// visited in reverse if `rev` is set. As with `(start .. end).step_by(step).rev()`,
+  /// reversal does not change which elements are selected.
+  Range{start: Bound<isize>, end: Bound<isize>, step: usize, rev: bool},
+}
+
+impl SliceSpec {
+  /// Selects the whole axis.
+  pub fn all() -> SliceSpec {
+    SliceSpec::from(..)
+  }
+
+  pub fn step(self, step: usize) -> SliceSpec {
+    assert!(step > 0, "SliceSpec: zero step");
+    match self {
+      SliceSpec::Range{start, end, step: s, rev} => match s.checked_mul(step) {
+        None => panic!("SliceSpec: step overflow: step: {} by: {}", s, step),
+        Some(step) => SliceSpec::Range{start, end, step, rev},
+      },
+      SliceSpec::Index(_) => panic!("SliceSpec: cannot step an index: {:?}", self),
+    }
+  }
+
+  pub fn rev(self) -> SliceSpec {
+    match self {
+      SliceSpec::Range{start, end, step, rev} => SliceSpec::Range{start, end, step, rev: !rev},
+      SliceSpec::Index(_) => panic!("SliceSpec: cannot reverse an index: {:?}", self),
+    }
+  }
+
+  fn from_bounds<R>(r: R) -> SliceSpec where R: RangeBounds<isize> {
+    SliceSpec::Range{
+      start:  r.start_bound().cloned(),
+      end:    r.end_bound().cloned(),
+      step:   1,
+      rev:    false,
+    }
+  }
+}
+
+impl From<isize> for SliceSpec {
+  fn from(i: isize) -> SliceSpec {
+    SliceSpec::Index(i)
+  }
+}
+
+impl From<RangeFull> for SliceSpec {
+  fn from(r: RangeFull) -> SliceSpec {
+    SliceSpec::from_bounds(r)
+  }
+}
+
+impl From<Range<isize>> for SliceSpec {
+  fn from(r: Range<isize>) -> SliceSpec {
+    SliceSpec::from_bounds(r)
+  }
+}
+
+impl From<RangeFrom<isize>> for SliceSpec {
+  fn from(r: RangeFrom<isize>) -> SliceSpec {
+    SliceSpec::from_bounds(r)
+  }
+}
+
+impl From<RangeTo<isize>> for SliceSpec {
+  fn from(r: RangeTo<isize>) -> SliceSpec {
+    SliceSpec::from_bounds(r)
+  }
+}
+
+impl From<RangeInclusive<isize>> for SliceSpec {
+  fn from(r: RangeInclusive<isize>) -> SliceSpec {
+    SliceSpec::from_bounds(r)
+  }
+}
+
+impl From<RangeToInclusive<isize>> for SliceSpec {
+  fn from(r: RangeToInclusive<isize>) -> SliceSpec {
+    SliceSpec::from_bounds(r)
+  }
+}
+
+// Applies `spec` to a layout, returning the size, stride and offset of the
+// result. Axes selected by `SliceSpec::Index` are dropped.
+fn slice_layout(size: &[usize], stride: &[usize], offset: usize, spec: &[SliceSpec]) -> (Vec<usize>, Vec<usize>, usize) {
+  assert_eq!(spec.len(), size.len(),
+      "slice: rank mismatch: spec: {:?} size: {:?}", spec, size);
+  let mut new_size = Vec::with_capacity(size.len());
+  let mut new_stride = Vec::with_capacity(size.len());
+  let mut new_offset = offset;
+  for d in 0 .. size.len() {
+    match spec[d] {
+      SliceSpec::Index(i) => {
+        let i = match i2idx(i, size[d]) {
+          None => panic!("slice: index out of bounds: index: {} axis: {} len: {}", i, d, size[d]),
+          Some(i) => i,
+        };
+        new_offset = new_offset.wrapping_add(i.wrapping_mul(stride[d]));
+      }
+      SliceSpec::Range{start, end, step, rev} => {
+        assert!(step > 0, "slice: zero step: axis: {}", d);
+        let (s, e) = srange2idx((start, end), size[d]);
+        let len = (e - s) / step + ((e - s) % step != 0) as usize;
+        let step_stride = stride[d].wrapping_mul(step);
+        let first = if rev && len > 0 { s + (len - 1) * step } else { s };
+        new_offset = new_offset.wrapping_add(first.wrapping_mul(stride[d]));
+        new_size.push(len);
+        new_stride.push(if rev { step_stride.wrapping_neg() } else { step_stride });
+      }
+    }
+  }
+  (new_size, new_stride, new_offset)
+}
+
+fn range2idx<R>(r: R, axis: usize, len: usize) -> (usize, usize) where R: RangeBounds<usize> {
+  let start = match r.start_bound() {
+    Bound::Included(&s) => Some(s),
+    Bound::Excluded(&s) => s.checked_add(1),
+    Bound::Unbounded => Some(0),
+  };
+  let end = match r.end_bound() {
+    Bound::Included(&e) => e.checked_add(1),
+    Bound::Excluded(&e) => Some(e),
+    Bound::Unbounded => Some(len),
+  };
+  match (start, end) {
+    (Some(start), Some(end)) if start <= end && end <= len => (start, end),
+    _ => panic!("view: range out of bounds: axis: {} start: {:?} end: {:?} len: {}",
+        axis, r.start_bound(), r.end_bound(), len),
+  }
+}
+
+/// One range per axis of an array with index type `Idx`, for `view` and
+/// `view_mut`: `()` for 0-d arrays, a single range for 1-d arrays, and a
+/// tuple of ranges otherwise.
+pub trait ViewRange<Idx> {
+  /// Returns the start and end indices of the ranges. Panics if a range is
+  /// out of bounds for `size`.
+  fn to_bounds(self, size: &Idx) -> (Idx, Idx);
+}
+
+/// Like `ViewRange`, but with numpy-style signed ranges, for `view_signed`
+/// and `view_mut_signed`: negative bounds count from the end of the axis,
+/// and out-of-range bounds are clamped.
+pub trait SignedViewRange<Idx> {
+  fn to_bounds(self, size: &Idx) -> (Idx, Idx);
+}
+
+impl ViewRange<Index0d> for () {
+  fn to_bounds(self, _size: &Index0d) -> (Index0d, Index0d) {
+    ((), ())
+  }
+}
+
+impl SignedViewRange<Index0d> for () {
+  fn to_bounds(self, _size: &Index0d) -> (Index0d, Index0d) {
+    ((), ())
+  }
+}
+
+impl<R> ViewRange<Index1d> for R where R: RangeBounds<usize> {
+  fn to_bounds(self, size: &Index1d) -> (Index1d, Index1d) {
+    range2idx(self, 0, *size)
+  }
+}
+
+impl<R> SignedViewRange<Index1d> for R where R: RangeBounds<isize> {
+  fn to_bounds(self, size: &Index1d) -> (Index1d, Index1d) {
+    srange2idx(self, *size)
+  }
+}
+
+macro_rules! impl_view_range {
+  ($nd:expr, $($r:ident: $d:tt),*) => {
+    impl<$($r),*> ViewRange<[usize; $nd]> for ($($r,)*) where $($r: RangeBounds<usize>),* {
+      fn to_bounds(self, size: &[usize; $nd]) -> ([usize; $nd], [usize; $nd]) {
+        let mut start = [0; $nd];
+        let mut end = [0; $nd];
+        $(
+          let (s, e) = range2idx(self.$d, $d, size[$d]);
+          start[$d] = s;
+          end[$d] = e;
+        )*
+        (start, end)
+      }
+    }
+
+    impl<$($r),*> SignedViewRange<[usize; $nd]> for ($($r,)*) where $($r: RangeBounds<isize>),* {
+      fn to_bounds(self, size: &[usize; $nd]) -> ([usize; $nd], [usize; $nd]) {
+        let mut start = [0; $nd];
+        let mut end = [0; $nd];
+        $(
+          let (s, e) = srange2idx(self.$d, size[$d]);
+          start[$d] = s;
+          end[$d] = e;
+        )*
+        (start, end)
+      }
+    }
+  };
+}
+
+impl_view_range!(2, R0: 0, R1: 1);
+impl_view_range!(3, R0: 0, R1: 1, R2: 2);
+impl_view_range!(4, R0: 0, R1: 1, R2: 2, R3: 3);
+impl_view_range!(5, R0: 0, R1: 1, R2: 2, R3: 3, R4: 4);
+
+// Computes strides for viewing an array of size `size` and stride `stride`
+// as an array of size `new_size` without copying, or returns `None` if the
+// layout does not allow it. This follows numpy's `_attempt_nocopy_reshape`,
+// adapted to the column-major (axis 0 innermost) layout used here.
+fn reshape_stride(size: &[usize], stride: &[usize], new_size: &[usize]) -> Option<Vec<usize>> {
+  let flat_len: usize = size.iter().product();
+  assert_eq!(flat_len, new_size.iter().product::<usize>(),
+      "reshape: size mismatch: old size: {:?} new size: {:?}", size, new_size);
+  let mut new_stride = vec![0; new_size.len()];
+  if flat_len == 0 {
+    let mut s = 1;
+    for d in 0 .. new_size.len() {
+      new_stride[d] = s;
+      s *= new_size[d];
+    }
+    return Some(new_stride);
+  }
+  // Axes of length 1 do not constrain the layout.
+  let (old_size, old_stride): (Vec<usize>, Vec<usize>) = size.iter().zip(stride.iter())
+    .filter(|&(&s, _)| s != 1)
+    .map(|(&s, &t)| (s, t))
+    .unzip();
+  let (mut oi, mut oj) = (0, 1);
+  let (mut ni, mut nj) = (0, 1);
+  while ni < new_size.len() && oi < old_size.len() {
+    let mut np = new_size[ni];
+    let mut op = old_size[oi];
+    while np != op {
+      if np < op {
+        np *= new_size[nj];
+        nj += 1;
+      } else {
+        op *= old_size[oj];
+        oj += 1;
+      }
+    }
+    for ok in oi .. oj - 1 {
+      if old_stride[ok + 1] != old_size[ok].wrapping_mul(old_stride[ok]) {
+        return None;
+      }
+    }
+    new_stride[ni] = old_stride[oi];
+    for nk in ni + 1 .. nj {
+      new_stride[nk] = new_stride[nk - 1].wrapping_mul(new_size[nk - 1]);
+    }
+    ni = nj;
+    nj += 1;
+    oi = oj;
+    oj += 1;
+  }
+  // Remaining axes all have length 1.
+  for nk in ni .. new_size.len() {
+    new_stride[nk] = if nk == 0 { 1 } else { new_stride[nk - 1].wrapping_mul(new_size[nk - 1]) };
+  }
+  Some(new_stride)
+}
+
+// Returns the offset in memory of the element at `idx`. Strides of reversed
+// axes are negative and stored in two's complement, hence the wrapping
+// arithmetic.
+fn elem_offset<Idx>(offset: usize, idx: &Idx, stride: &Idx) -> usize where Idx: ArrayIndex {
+  idx.to_nd().iter().zip(stride.to_nd().iter())
+    .fold(offset, |off, (&i, &s)| off.wrapping_add(i.wrapping_mul(s)))
+}
+
+// Like `elem_offset`, but returns `None` if `idx` is out of bounds.
+fn checked_elem_offset<Idx>(offset: usize, idx: &Idx, size: &Idx, stride: &Idx) -> Option<usize> where Idx: ArrayIndex {
+  if idx.to_nd().iter().zip(size.to_nd().iter()).any(|(&i, &s)| i >= s) {
+    return None;
+  }
+  Some(elem_offset(offset, idx, stride))
+}
+
+// Calls `f` with the flat offset of every element of a strided array, in
+// column-major order.
+fn for_each_flat_offset<F>(size: &[usize], stride: &[usize], offset: usize, mut f: F) where F: FnMut(usize) {
+  if size.iter().any(|&s| s == 0) {
+    return;
+  }
+  let nd = size.len();
+  let mut idx = vec![0; nd];
+  let mut off = offset;
+  loop {
+    f(off);
+    let mut d = 0;
+    loop {
+      if d == nd {
+        return;
+      }
+      idx[d] += 1;
+      off = off.wrapping_add(stride[d]);
+      if idx[d] < size[d] {
+        break;
+      }
+      off = off.wrapping_sub(stride[d].wrapping_mul(size[d]));
+      idx[d] = 0;
+      d += 1;
+    }
+  }
+}
+
+// Like `for_each_flat_offset`, but walks several arrays of the same size at
+// once, calling `f` with the offsets of the element at each index in every
+// array.
+fn for_each_flat_offset_n<F>(size: &[usize], strides: &[&[usize]], offsets: &[usize], mut f: F) where F: FnMut(&[usize]) {
+  if size.iter().any(|&s| s == 0) {
+    return;
+  }
+  let nd = size.len();
+  let mut idx = vec![0; nd];
+  let mut off = offsets.to_vec();
+  loop {
+    f(&off);
+    let mut d = 0;
+    loop {
+      if d == nd {
+        return;
+      }
+      idx[d] += 1;
+      for (o, stride) in off.iter_mut().zip(strides.iter()) {
+        *o = o.wrapping_add(stride[d]);
+      }
+      if idx[d] < size[d] {
+        break;
+      }
+      for (o, stride) in off.iter_mut().zip(strides.iter()) {
+        *o = o.wrapping_sub(stride[d].wrapping_mul(size[d]));
+      }
+      idx[d] = 0;
+      d += 1;
+    }
+  }
+}
+
+// Returns the number of leading axes along which every layout in `strides`
+// is packed, and the number of elements they span. The elements along those
+// axes form one contiguous run in every array.
+fn packed_prefix(size: &[usize], strides: &[&[usize]]) -> (usize, usize) {
+  let mut run = 1;
+  for d in 0 .. size.len() {
+    if size[d] != 1 && strides.iter().any(|stride| stride[d] != run) {
+      return (d, run);
+    }
+    run *= size[d];
+  }
+  (size.len(), run)
+}
+
+// Computes strides for viewing an array of size `size` and stride `stride`
+// as an array of size `new_size` by broadcasting, or returns `None` if the
+// sizes are incompatible. Sizes are aligned at axis 0, the innermost axis,
+// as numpy aligns them at its innermost (last) axis. Axes of length 1, and
+// axes beyond the rank of `size`, are repeated with a zero stride.
+fn broadcast_stride(size: &[usize], stride: &[usize], new_size: &[usize]) -> Option<Vec<usize>> {
+  if size.len() > new_size.len() {
+    return None;
+  }
+  let mut new_stride = vec![0; new_size.len()];
+  for d in 0 .. size.len() {
+    if size[d] == new_size[d] {
+      new_stride[d] = stride[d];
+    } else if size[d] != 1 {
+      return None;
+    }
+  }
+  Some(new_stride)
+}
+
+// Permutes the axes of a layout, so that axis `d` of the result is axis
+// `perm[d]` of the original. Panics if `perm` is not a permutation.
+fn permute_layout<Idx>(size: &Idx, stride: &Idx, perm: &[usize]) -> (Idx, Idx) where Idx: ArrayIndex {
+  let (size, stride) = (size.to_nd(), stride.to_nd());
+  let mut seen = vec![false; size.len()];
+  assert_eq!(perm.len(), size.len(), "permute_axes: rank mismatch: perm: {:?} size: {:?}", perm, size);
+  for &p in perm.iter() {
+    assert!(p < size.len() && !seen[p], "permute_axes: invalid permutation: {:?}", perm);
+    seen[p] = true;
+  }
+  let new_size = perm.iter().map(|&p| size[p]).collect();
+  let new_stride = perm.iter().map(|&p| stride[p]).collect();
+  (Idx::from_nd(new_size), Idx::from_nd(new_stride))
+}
+
+fn swap_perm(nd: usize, a: usize, b: usize) -> Vec<usize> {
+  assert!(a < nd && b < nd, "swap_axes: axis out of bounds: a: {} b: {} ndim: {}", a, b, nd);
+  let mut perm: Vec<usize> = (0 .. nd).collect();
+  perm.swap(a, b);
+  perm
 }
 
 pub trait ReadOnlyMem<T> where T: Copy {
   unsafe fn as_ptr(&self) -> *const T;
   fn as_slice(&self) -> &[T];
   fn as_bytes(&self) -> &[u8];
+
+  /// Panics if the guard bytes around the memory have been overwritten,
+  /// naming the owning array with `desc`. Only `HeapMem` (and the types
+  /// wrapping it) have guard bytes, and only with the "guard" feature.
+  fn check_guards_with(&self, _desc: &dyn Fn() -> String) {
+  }
 }
 
 pub trait Mem<T>: ReadOnlyMem<T> where T: Copy {
@@ -80,40 +572,187 @@ impl<T> ReadOnlyMem<T> for SharedMem<T> where T: Copy {
   }
 }
 
-pub struct HeapMem<T> where T: Copy {
-  buf:  *mut T,
-  len:  usize,
-  phsz: usize,
+/// Allocator for `HeapMem` buffers. With the "nightly" feature, this is
+/// implemented for every `std::alloc::Allocator`.
+pub unsafe trait Alloc {
+  /// Returns null on failure. `layout` never has zero size.
+  unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
+  unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+}
+
+/// The global allocator, as used by `std::alloc::alloc`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Global;
+
+unsafe impl Alloc for Global {
+  unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+    std::alloc::alloc(layout)
+  }
+
+  unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+    std::alloc::dealloc(ptr, layout)
+  }
+}
+
+#[cfg(feature = "nightly")]
+unsafe impl<A> Alloc for A where A: std::alloc::Allocator {
+  unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+    match self.allocate(layout) {
+      Err(_) => null_mut(),
+      Ok(p) => p.as_ptr() as *mut u8,
+    }
+  }
+
+  unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+    self.deallocate(std::ptr::NonNull::new_unchecked(ptr), layout)
+  }
+}
+
+pub struct HeapMem<T, A=Global> where T: Copy, A: Alloc {
+  buf:    *mut T,
+  len:    usize,
+  phsz:   usize,
+  acct:   MemAccount,
+  alloc:  A,
 }
 
-impl<T> Drop for HeapMem<T> where T: Copy {
+// `HeapMem` owns its buffer exclusively, like a `Vec<T>`.
+unsafe impl<T, A> Send for HeapMem<T, A> where T: Copy + Send, A: Alloc + Send {}
+unsafe impl<T, A> Sync for HeapMem<T, A> where T: Copy + Sync, A: Alloc + Sync {}
+
+impl<T, A> Drop for HeapMem<T, A> where T: Copy, A: Alloc {
   fn drop(&mut self) {
     assert!(!self.buf.is_null());
-    let p = unsafe { NonNull::new_unchecked(self.buf) };
-    self.buf = null_mut();
-    match unsafe { Global::default().dealloc_array(p, self.len) } {
-      Err(_) => panic!(),
-      Ok(_) => {}
+    // Avoid a double panic (and an abort) when unwinding.
+    if !std::thread::panicking() {
+      self.check_guards();
     }
+    unsafe { heap_dealloc(&mut self.alloc, self.buf, self.len) };
+    self.buf = null_mut();
+  }
+}
+
+#[cfg(not(feature = "guard"))]
+unsafe fn heap_alloc<T, A>(alloc: &mut A, len: usize) -> *mut T where T: Copy, A: Alloc {
+  let layout = Layout::array::<T>(len).unwrap();
+  if layout.size() == 0 {
+    return std::ptr::NonNull::dangling().as_ptr();
+  }
+  let p = alloc.alloc(layout);
+  if p.is_null() {
+    handle_alloc_error(layout);
+  }
+  p as *mut T
+}
+
+#[cfg(not(feature = "guard"))]
+unsafe fn heap_dealloc<T, A>(alloc: &mut A, buf: *mut T, len: usize) where T: Copy, A: Alloc {
+  let layout = Layout::array::<T>(len).unwrap();
+  if layout.size() == 0 {
+    return;
+  }
+  alloc.dealloc(buf as *mut u8, layout);
+}
+
+#[cfg(feature = "guard")]
+const GUARD_LEN:  usize = 64;
+#[cfg(feature = "guard")]
+const GUARD_BYTE: u8 = 0xa5;
+
+// With the "guard" feature, every buffer is surrounded by `GUARD_LEN` canary
+// bytes on each side, which are checked when the buffer is freed.
+#[cfg(feature = "guard")]
+fn guard_layout<T>(len: usize) -> Layout {
+  assert!(std::mem::align_of::<T>() <= GUARD_LEN);
+  Layout::from_size_align(GUARD_LEN + len * size_of::<T>() + GUARD_LEN, GUARD_LEN).unwrap()
+}
+
+#[cfg(feature = "guard")]
+unsafe fn heap_alloc<T, A>(alloc: &mut A, len: usize) -> *mut T where T: Copy, A: Alloc {
+  let layout = guard_layout::<T>(len);
+  let base = alloc.alloc(layout);
+  if base.is_null() {
+    handle_alloc_error(layout);
   }
+  let phsz = len * size_of::<T>();
+  write_bytes(base, GUARD_BYTE, GUARD_LEN);
+  write_bytes(base.offset((GUARD_LEN + phsz) as isize), GUARD_BYTE, GUARD_LEN);
+  base.offset(GUARD_LEN as isize) as *mut T
+}
+
+#[cfg(feature = "guard")]
+unsafe fn heap_dealloc<T, A>(alloc: &mut A, buf: *mut T, len: usize) where T: Copy, A: Alloc {
+  let base = (buf as *mut u8).offset(-(GUARD_LEN as isize));
+  alloc.dealloc(base, guard_layout::<T>(len));
 }
 
 impl<T> HeapMem<T> where T: Copy {
   pub unsafe fn alloc(len: usize) -> Self {
+    HeapMem::alloc_in(len, Global)
+  }
+}
+
+impl<T, A> HeapMem<T, A> where T: Copy, A: Alloc {
+  /// Like `alloc`, but allocates the buffer from `alloc` instead of the
+  /// global allocator.
+  pub unsafe fn alloc_in(len: usize, mut alloc: A) -> Self {
     let phsz = len * size_of::<T>();
-    let p = match Global::default().alloc_array(len) {
-      Err(_) => panic!(),
-      Ok(p) => p,
-    };
+    let p = heap_alloc::<T, A>(&mut alloc, len);
     HeapMem{
-      buf:  p.as_ptr(),
-      len:  len,
-      phsz: phsz,
+      buf:    p,
+      len:    len,
+      phsz:   phsz,
+      acct:   MemAccount::new(phsz),
+      alloc:  alloc,
+    }
+  }
+
+  pub fn allocator(&self) -> &A {
+    &self.alloc
+  }
+
+  /// Panics if the guard bytes on either side of the buffer have been
+  /// overwritten. Does nothing unless the "guard" feature is enabled.
+  #[cfg(feature = "guard")]
+  pub fn check_guards(&self) {
+    self.check_guards_desc(None);
+  }
+
+  #[cfg(feature = "guard")]
+  fn check_guards_desc(&self, desc: Option<&dyn Fn() -> String>) {
+    let before = unsafe { from_raw_parts((self.buf as *const u8).offset(-(GUARD_LEN as isize)), GUARD_LEN) };
+    let after = unsafe { from_raw_parts((self.buf as *const u8).offset(self.phsz as isize), GUARD_LEN) };
+    let side = if before.iter().any(|&b| b != GUARD_BYTE) {
+      "before"
+    } else if after.iter().any(|&b| b != GUARD_BYTE) {
+      "after"
+    } else {
+      return;
+    };
+    match desc {
+      None => panic!("HeapMem: guard bytes {} the buffer were overwritten: len: {} bytes: {}",
+          side, self.len, self.phsz),
+      Some(desc) => panic!("HeapMem: guard bytes {} the buffer were overwritten: len: {} bytes: {} array: {}",
+          side, self.len, self.phsz, desc()),
     }
   }
+
+  #[cfg(not(feature = "guard"))]
+  pub fn check_guards(&self) {
+  }
 }
 
-impl<T> ReadOnlyMem<T> for HeapMem<T> where T: Copy {
+impl<T, A> TagMem for HeapMem<T, A> where T: Copy, A: Alloc {
+  fn tag(&self) -> &'static str {
+    self.acct.tag()
+  }
+
+  fn set_tag(&mut self, tag: &'static str) {
+    self.acct.set_tag(tag);
+  }
+}
+
+impl<T, A> ReadOnlyMem<T> for HeapMem<T, A> where T: Copy, A: Alloc {
   unsafe fn as_ptr(&self) -> *const T {
     self.buf
   }
@@ -123,11 +762,16 @@ impl<T> ReadOnlyMem<T> for HeapMem<T> where T: Copy {
   }
 
   fn as_bytes(&self) -> &[u8] {
-    unsafe { from_raw_parts(self.buf as _, self.len) }
+    unsafe { from_raw_parts(self.buf as *const u8, self.phsz) }
+  }
+
+  #[cfg(feature = "guard")]
+  fn check_guards_with(&self, desc: &dyn Fn() -> String) {
+    self.check_guards_desc(Some(desc));
   }
 }
 
-impl<T> Mem<T> for HeapMem<T> where T: Copy {
+impl<T, A> Mem<T> for HeapMem<T, A> where T: Copy, A: Alloc {
   unsafe fn as_mut_ptr(&mut self) -> *mut T {
     self.buf
   }
@@ -137,7 +781,134 @@ impl<T> Mem<T> for HeapMem<T> where T: Copy {
   }
 
   fn as_mut_bytes(&mut self) -> &mut [u8] {
-    unsafe { from_raw_parts_mut(self.buf as _, self.len) }
+    unsafe { from_raw_parts_mut(self.buf as *mut u8, self.phsz) }
+  }
+}
+
+/// Reference-counted heap memory which can be read from many threads at once.
+/// Mutable access is only granted while the memory is uniquely owned, and
+/// panics otherwise; see `MemArray::try_unique_mut`.
+#[derive(Clone)]
+pub struct ArcMem<T> where T: Copy {
+  buf:  Arc<HeapMem<T>>,
+}
+
+impl<T> ArcMem<T> where T: Copy {
+  pub fn new(mem: HeapMem<T>) -> Self {
+    ArcMem{buf: Arc::new(mem)}
+  }
+
+  pub fn is_unique(&self) -> bool {
+    Arc::strong_count(&self.buf) == 1
+  }
+
+  pub fn try_unwrap(self) -> Result<HeapMem<T>, Self> {
+    Arc::try_unwrap(self.buf).map_err(|buf| ArcMem{buf})
+  }
+
+  fn unique_mut(&mut self) -> &mut HeapMem<T> {
+    match Arc::get_mut(&mut self.buf) {
+      None => panic!("ArcMem: mutable access to memory that is shared"),
+      Some(mem) => mem,
+    }
+  }
+}
+
+impl<T> ReadOnlyMem<T> for ArcMem<T> where T: Copy {
+  unsafe fn as_ptr(&self) -> *const T {
+    self.buf.as_ptr()
+  }
+
+  fn as_slice(&self) -> &[T] {
+    self.buf.as_slice()
+  }
+
+  fn as_bytes(&self) -> &[u8] {
+    self.buf.as_bytes()
+  }
+
+  fn check_guards_with(&self, desc: &dyn Fn() -> String) {
+    self.buf.check_guards_with(desc);
+  }
+}
+
+impl<T> Mem<T> for ArcMem<T> where T: Copy {
+  unsafe fn as_mut_ptr(&mut self) -> *mut T {
+    self.unique_mut().as_mut_ptr()
+  }
+
+  fn as_mut_slice(&mut self) -> &mut [T] {
+    self.unique_mut().as_mut_slice()
+  }
+
+  fn as_mut_bytes(&mut self) -> &mut [u8] {
+    self.unique_mut().as_mut_bytes()
+  }
+}
+
+/// Copy-on-write heap memory. Clones share the same buffer, and the first
+/// mutable access through a clone whose buffer is still shared makes a
+/// private copy of it.
+#[derive(Clone)]
+pub struct CowMem<T> where T: Copy {
+  buf:  Arc<HeapMem<T>>,
+}
+
+impl<T> CowMem<T> where T: Copy {
+  pub fn new(mem: HeapMem<T>) -> Self {
+    CowMem{buf: Arc::new(mem)}
+  }
+
+  pub fn is_shared(&self) -> bool {
+    Arc::strong_count(&self.buf) > 1
+  }
+
+  // Kept out of line so that copies are easy to find when profiling.
+  #[inline(never)]
+  fn copy_shared(&mut self) {
+    let mut mem = unsafe { HeapMem::<T>::alloc(self.buf.len) };
+    mem.set_tag(self.buf.tag());
+    mem.as_mut_slice().copy_from_slice(self.buf.as_slice());
+    self.buf = Arc::new(mem);
+  }
+
+  fn unique_mut(&mut self) -> &mut HeapMem<T> {
+    if Arc::get_mut(&mut self.buf).is_none() {
+      self.copy_shared();
+    }
+    Arc::get_mut(&mut self.buf).unwrap()
+  }
+}
+
+impl<T> ReadOnlyMem<T> for CowMem<T> where T: Copy {
+  unsafe fn as_ptr(&self) -> *const T {
+    self.buf.as_ptr()
+  }
+
+  fn as_slice(&self) -> &[T] {
+    self.buf.as_slice()
+  }
+
+  fn as_bytes(&self) -> &[u8] {
+    self.buf.as_bytes()
+  }
+
+  fn check_guards_with(&self, desc: &dyn Fn() -> String) {
+    self.buf.check_guards_with(desc);
+  }
+}
+
+impl<T> Mem<T> for CowMem<T> where T: Copy {
+  unsafe fn as_mut_ptr(&mut self) -> *mut T {
+    self.unique_mut().as_mut_ptr()
+  }
+
+  fn as_mut_slice(&mut self) -> &mut [T] {
+    self.unique_mut().as_mut_slice()
+  }
+
+  fn as_mut_bytes(&mut self) -> &mut [u8] {
+    self.unique_mut().as_mut_bytes()
   }
 }
 
@@ -180,15 +951,41 @@ pub trait Array: Shape {
 }
 
 pub trait DenseArray: Array {
-  fn offset(&self) -> Self::Idx;
+  /// The offset of the first element, in elements from the start of the
+  /// underlying memory.
+  fn flat_offset(&self) -> usize;
+  /// The stride of each axis, in elements. Reversed axes have negative
+  /// strides, which are stored in two's complement (cast to `isize` to read
+  /// them), and broadcast axes have zero strides.
   fn stride(&self) -> Self::Idx;
 
-  fn flat_offset(&self) -> usize {
-    self.offset().flat_index(&self.stride())
+  /// The offset of the first element as an index, such that
+  /// `offset().flat_index(&stride())` is `flat_offset()`. Panics if the
+  /// offset cannot be written that way, e.g. for some reversed views.
+  #[deprecated(note = "use `flat_offset`, which every layout can express")]
+  fn offset(&self) -> Self::Idx {
+    let stride = self.stride().to_nd();
+    let mut axes: Vec<_> = (0 .. stride.len()).filter(|&d| stride[d] as isize > 0).collect();
+    axes.sort_by(|&a, &b| stride[b].cmp(&stride[a]));
+    let mut idx = vec![0; stride.len()];
+    let mut rem = self.flat_offset();
+    for d in axes {
+      idx[d] = rem / stride[d];
+      rem %= stride[d];
+    }
+    if rem != 0 {
+      panic!("DenseArray::offset: offset is not an index: flat offset: {} stride: {:?}",
+          self.flat_offset(), self.stride());
+    }
+    Self::Idx::from_nd(idx)
   }
 
+  /// Whether the elements are contiguous in memory, in column-major order.
+  /// Strides of axes of length 1 are ignored, and broadcast (zero stride)
+  /// axes are never packed.
   fn is_packed(&self) -> bool {
-    self.size().is_packed(&self.stride())
+    let size = self.size().to_nd();
+    packed_prefix(&size, &[&self.stride().to_nd()]).0 == size.len()
   }
 }
 
@@ -205,10 +1002,52 @@ pub trait BatchArray: Array {
 #[derive(Clone)]
 pub struct MemArray<Idx, T, M=HeapMem<T>> where T: Copy {
   size:     Idx,
-  offset:   Idx,
+  offset:   usize,
   stride:   Idx,
   mem:      M,
-  _mrk:     PhantomData<*mut T>,
+  #[cfg(feature = "guard")]
+  guard:    fn(&M, &Idx, &Idx),
+  _mrk:     PhantomData<T>,
+}
+
+// A `Drop` impl cannot require `M: ReadOnlyMem<T>`, so the guard check run
+// on drop is instantiated when the array is built. The description of the
+// array is only formatted if the check fails.
+#[cfg(feature = "guard")]
+fn check_array_guards<Idx, T, M>(mem: &M, size: &Idx, stride: &Idx) where Idx: ArrayIndex, T: Copy, M: ReadOnlyMem<T> {
+  mem.check_guards_with(&|| format!("size: {:?} stride: {:?}", size, stride));
+}
+
+#[cfg(feature = "guard")]
+impl<Idx, T, M> Drop for MemArray<Idx, T, M> where T: Copy {
+  fn drop(&mut self) {
+    // Avoid a double panic (and an abort) when unwinding.
+    if !std::thread::panicking() {
+      (self.guard)(&self.mem, &self.size, &self.stride);
+    }
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyMem<T> {
+  fn from_parts(size: Idx, offset: usize, stride: Idx, mem: M) -> Self {
+    MemArray{
+      size:     size,
+      offset:   offset,
+      stride:   stride,
+      mem:      mem,
+      #[cfg(feature = "guard")]
+      guard:    check_array_guards::<Idx, T, M>,
+      _mrk:     PhantomData,
+    }
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where T: Copy {
+  // Moves the fields out of the array, without running its drop check.
+  fn into_parts(self) -> (Idx, usize, Idx, M) {
+    let arr = ManuallyDrop::new(self);
+    unsafe { (read(&arr.size), arr.offset, read(&arr.stride), read(&arr.mem)) }
+  }
 }
 
 pub type MemScalar<T>  = MemArray0d<T>;
@@ -219,20 +1058,11 @@ pub type MemArray3d<T> = MemArray<Index3d, T>;
 pub type MemArray4d<T> = MemArray<Index4d, T>;
 pub type MemArray5d<T> = MemArray<Index5d, T>;
 
-unsafe impl<Idx, T, M> Send for MemArray<Idx, T, M> where Idx: Send + Sync, T: Copy, M: Send + Sync {}
-unsafe impl<Idx, T, M> Sync for MemArray<Idx, T, M> where Idx: Send + Sync, T: Copy, M: Send + Sync {}
-
 impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyMem<T> {
   pub fn with_memory(size: Idx, mem: M) -> Self {
     assert_eq!(size.flat_len(), mem.as_slice().len());
     let stride = size.to_packed_stride();
-    MemArray{
-      size:     size,
-      offset:   Idx::zero(),
-      stride:   stride,
-      mem:      mem,
-      _mrk:     PhantomData,
-    }
+    MemArray::from_parts(size, 0, stride, mem)
   }
 }
 
@@ -243,16 +1073,92 @@ impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyM
 
 impl<Idx, T> MemArray<Idx, T> where Idx: ArrayIndex, T: ZeroBits {
   pub fn zeros(size: Idx) -> Self {
-    let mem = unsafe { HeapMem::<T>::alloc(size.flat_len()) };
+    MemArray::zeros_in(size, Global)
+  }
+}
+
+impl<Idx, T, A> MemArray<Idx, T, HeapMem<T, A>> where Idx: ArrayIndex, T: ZeroBits, A: Alloc {
+  /// Like `zeros`, but allocates the memory from `alloc` instead of the
+  /// global allocator.
+  pub fn zeros_in(size: Idx, alloc: A) -> Self {
+    let mem = unsafe { HeapMem::<T, A>::alloc_in(size.flat_len(), alloc) };
     // The memory is uninitialized, zero it using memset.
     unsafe { write_bytes::<T>(mem.buf, 0, mem.len) };
     let stride = size.to_packed_stride();
-    MemArray{
-      size:     size,
-      offset:   Idx::zero(),
-      stride:   stride,
-      mem:      mem,
-      _mrk:     PhantomData,
+    MemArray::from_parts(size, 0, stride, mem)
+  }
+}
+
+impl<Idx, T> MemArray<Idx, T> where Idx: ArrayIndex, T: ZeroBits {
+  /// Like `zeros`, and attributes the memory to `tag` in the allocation
+  /// accounting.
+  pub fn zeros_tagged(size: Idx, tag: &'static str) -> Self {
+    MemArray::zeros(size).with_tag(tag)
+  }
+}
+
+impl<Idx, T, A> MemArray<Idx, T, HeapMem<T, A>> where Idx: ArrayIndex, T: Copy, A: Alloc {
+  /// Panics, naming this array's size and stride, if the guard bytes around
+  /// its memory have been overwritten. Does nothing unless the "guard"
+  /// feature is enabled.
+  #[cfg(feature = "guard")]
+  pub fn check_guards(&self) {
+    check_array_guards(&self.mem, &self.size, &self.stride);
+  }
+
+  #[cfg(not(feature = "guard"))]
+  pub fn check_guards(&self) {
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: TagMem {
+  pub fn with_tag(mut self, tag: &'static str) -> Self {
+    self.mem.set_tag(tag);
+    self
+  }
+
+  pub fn tag(&self) -> &'static str {
+    self.mem.tag()
+  }
+}
+
+impl<Idx, T> MemArray<Idx, T> where Idx: ArrayIndex, T: Copy {
+  pub fn into_shared(self) -> MemArray<Idx, T, ArcMem<T>> {
+    let (size, offset, stride, mem) = self.into_parts();
+    MemArray::from_parts(size, offset, stride, ArcMem::new(mem))
+  }
+
+  pub fn into_cow(self) -> MemArray<Idx, T, CowMem<T>> {
+    let (size, offset, stride, mem) = self.into_parts();
+    MemArray::from_parts(size, offset, stride, CowMem::new(mem))
+  }
+}
+
+impl<Idx, T> MemArray<Idx, T, ArcMem<T>> where Idx: ArrayIndex, T: Copy {
+  /// Returns another handle to the same memory; this does not copy.
+  pub fn share(&self) -> Self {
+    self.clone()
+  }
+
+  pub fn is_unique(&self) -> bool {
+    self.mem.is_unique()
+  }
+
+  /// Returns a mutable view if no other handle shares this array's memory.
+  pub fn try_unique_mut<'a>(&'a mut self) -> Option<MemArrayViewMut<'a, Idx, T>> {
+    if !self.mem.is_unique() {
+      return None;
+    }
+    Some(self.as_view_mut())
+  }
+
+  /// Converts back into an unshared array if no other handle shares this
+  /// array's memory.
+  pub fn try_into_unique(self) -> Result<MemArray<Idx, T>, Self> {
+    let (size, offset, stride, mem) = self.into_parts();
+    match mem.try_unwrap() {
+      Err(mem) => Err(MemArray::from_parts(size, offset, stride, mem)),
+      Ok(mem) => Ok(MemArray::from_parts(size, offset, stride, mem)),
     }
   }
 }
@@ -266,9 +1172,29 @@ impl<Idx, T, M> Shape for MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy {
 }
 
 impl<Idx, T, M> Reshape for MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy {
+  /// Panics if the new size has a different number of elements, or if the
+  /// array's layout cannot be reshaped without a copy.
   fn reshape(&mut self, new_size: Idx) {
-    // FIXME
-    unimplemented!();
+    let new_stride = match reshape_stride(&self.size.to_nd(), &self.stride.to_nd(), &new_size.to_nd()) {
+      None => panic!("MemArray::reshape: layout is not compatible: size: {:?} stride: {:?} new size: {:?}",
+          self.size, self.stride, new_size),
+      Some(new_stride) => Idx::from_nd(new_stride),
+    };
+    self.size = new_size;
+    self.stride = new_stride;
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyMem<T> {
+  /// Like `reshape`, but the new size may have a different rank.
+  pub fn reshape_into<NewIdx>(self, new_size: NewIdx) -> MemArray<NewIdx, T, M> where NewIdx: ArrayIndex {
+    let new_stride = match reshape_stride(&self.size.to_nd(), &self.stride.to_nd(), &new_size.to_nd()) {
+      None => panic!("MemArray::reshape_into: layout is not compatible: size: {:?} stride: {:?} new size: {:?}",
+          self.size, self.stride, new_size),
+      Some(new_stride) => NewIdx::from_nd(new_stride),
+    };
+    let (_, offset, _, mem) = self.into_parts();
+    MemArray::from_parts(new_size, offset, new_stride, mem)
   }
 }
 
@@ -281,8 +1207,8 @@ impl<Idx, T, M> Array for MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy {
 }
 
 impl<Idx, T, M> DenseArray for MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy {
-  fn offset(&self) -> Idx {
-    self.offset.clone()
+  fn flat_offset(&self) -> usize {
+    self.offset
   }
 
   fn stride(&self) -> Idx {
@@ -299,10 +1225,28 @@ impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyM
     &self.mem
   }
 
+  pub fn get(&self, idx: Idx) -> Option<&T> {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => None,
+      Some(off) => Some(&self.mem.as_slice()[off]),
+    }
+  }
+
+  pub unsafe fn get_unchecked(&self, idx: Idx) -> &T {
+    &*self.mem.as_ptr().offset((elem_offset(self.offset, &idx, &self.stride)) as isize)
+  }
+
+  pub fn get_signed<I>(&self, idx: I) -> Option<&T> where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => None,
+      Some(idx) => Some(&self.mem.as_slice()[elem_offset(self.offset, &idx, &self.stride)]),
+    }
+  }
+
   pub fn as_view<'a>(&'a self) -> MemArrayView<'a, Idx, T> {
     MemArrayView{
       size:     self.size.clone(),
-      offset:   self.offset.clone(),
+      offset:   self.offset,
       stride:   self.stride.clone(),
       mem:      &self.mem,
     }
@@ -322,6 +1266,27 @@ impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyM
       None
     }
   }
+
+  pub fn view<'a, R>(&'a self, r: R) -> MemArrayView<'a, Idx, T> where R: ViewRange<Idx> {
+    self.as_view().view(r)
+  }
+
+  pub fn view_signed<'a, R>(&'a self, r: R) -> MemArrayView<'a, Idx, T> where R: SignedViewRange<Idx> {
+    self.as_view().view_signed(r)
+  }
+
+  /// Returns a view whose axis `d` is axis `perm[d]` of this array.
+  pub fn permute_axes<'a>(&'a self, perm: Idx) -> MemArrayView<'a, Idx, T> {
+    self.as_view().permute_axes(perm)
+  }
+
+  pub fn swap_axes<'a>(&'a self, a: usize, b: usize) -> MemArrayView<'a, Idx, T> {
+    self.as_view().swap_axes(a, b)
+  }
+
+  pub fn broadcast_to<'a, NewIdx>(&'a self, new_size: NewIdx) -> Result<MemArrayView<'a, NewIdx, T>, ()> where NewIdx: ArrayIndex {
+    self.as_view().broadcast_to(new_size)
+  }
 }
 
 impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: Mem<T> {
@@ -333,36 +1298,88 @@ impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: Mem<T> {
     &mut self.mem
   }
 
-  pub fn as_view_mut<'a>(&'a mut self) -> MemArrayViewMut<'a, Idx, T> {
-    MemArrayViewMut{
-      size:     self.size.clone(),
-      offset:   self.offset.clone(),
-      stride:   self.stride.clone(),
-      mem:      &mut self.mem,
+  pub fn get_mut(&mut self, idx: Idx) -> Option<&mut T> {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => None,
+      Some(off) => Some(&mut self.mem.as_mut_slice()[off]),
+    }
+  }
+
+  pub unsafe fn get_unchecked_mut(&mut self, idx: Idx) -> &mut T {
+    &mut *self.mem.as_mut_ptr().offset((elem_offset(self.offset, &idx, &self.stride)) as isize)
+  }
+
+  pub fn get_signed_mut<I>(&mut self, idx: I) -> Option<&mut T> where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => None,
+      Some(idx) => Some(&mut self.mem.as_mut_slice()[elem_offset(self.offset, &idx, &self.stride)]),
+    }
+  }
+
+  pub fn set_signed<I>(&mut self, idx: I, value: T) where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => panic!("index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(idx) => self[idx] = value,
     }
   }
 
+  pub fn set(&mut self, idx: Idx, value: T) {
+    self[idx] = value;
+  }
+
+  pub unsafe fn set_unchecked(&mut self, idx: Idx, value: T) {
+    *self.get_unchecked_mut(idx) = value;
+  }
+
+  pub fn as_view_mut<'a>(&'a mut self) -> MemArrayViewMut<'a, Idx, T> {
+    MemArrayViewMut::new(self.size.clone(), self.offset, self.stride.clone(), &mut self.mem)
+  }
+
+  pub fn view_mut<'a, R>(&'a mut self, r: R) -> MemArrayViewMut<'a, Idx, T> where R: ViewRange<Idx> {
+    self.as_view_mut().view_mut(r)
+  }
+
+  pub fn view_mut_signed<'a, R>(&'a mut self, r: R) -> MemArrayViewMut<'a, Idx, T> where R: SignedViewRange<Idx> {
+    self.as_view_mut().view_mut_signed(r)
+  }
+
   pub fn flat_view_mut<'a>(&'a mut self) -> Option<MemArrayViewMut1d<'a, T>> {
     if self.is_packed() {
       let flat_size = self.flat_size();
       let flat_offset = self.flat_offset();
-      Some(MemArrayViewMut{
-        size:       flat_size,
-        offset:     flat_offset,
-        stride:     flat_size.to_packed_stride(),
-        mem:        &mut self.mem,
-      })
+      Some(MemArrayViewMut::new(flat_size, flat_offset, flat_size.to_packed_stride(), &mut self.mem))
     } else {
       None
     }
   }
 }
 
+impl<Idx, T, M> Index<Idx> for MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: ReadOnlyMem<T> {
+  type Output = T;
+
+  fn index(&self, idx: Idx) -> &T {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => panic!("MemArray: index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(off) => &self.mem.as_slice()[off],
+    }
+  }
+}
+
+impl<Idx, T, M> IndexMut<Idx> for MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy, M: Mem<T> {
+  fn index_mut(&mut self, idx: Idx) -> &mut T {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => panic!("MemArray: index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(off) => &mut self.mem.as_mut_slice()[off],
+    }
+  }
+}
+
+#[derive(Clone)]
 pub struct MemArrayView<'a, Idx, T> where /*Idx: 'static,*/ T: Copy + 'static {
   size:     Idx,
-  offset:   Idx,
+  offset:   usize,
   stride:   Idx,
-  mem:      &'a ReadOnlyMem<T>,
+  mem:      &'a dyn ReadOnlyMem<T>,
 }
 
 pub type MemArrayView0d<'a, T> = MemArrayView<'a, Index0d, T>;
@@ -389,8 +1406,8 @@ impl<'a, Idx, T> Array for MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Co
 }
 
 impl<'a, Idx, T> DenseArray for MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
-  fn offset(&self) -> Idx {
-    self.offset.clone()
+  fn flat_offset(&self) -> usize {
+    self.offset
   }
 
   fn stride(&self) -> Idx {
@@ -403,25 +1420,37 @@ impl<'a, Idx, T> MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'stat
     self.mem.as_ptr().offset(self.flat_offset() as _)
   }
 
+  pub fn get(&self, idx: Idx) -> Option<&T> {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => None,
+      Some(off) => Some(&self.mem.as_slice()[off]),
+    }
+  }
+
+  pub unsafe fn get_unchecked(&self, idx: Idx) -> &T {
+    &*self.mem.as_ptr().offset((elem_offset(self.offset, &idx, &self.stride)) as isize)
+  }
+
+  pub fn get_signed<I>(&self, idx: I) -> Option<&T> where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => None,
+      Some(idx) => Some(&self.mem.as_slice()[elem_offset(self.offset, &idx, &self.stride)]),
+    }
+  }
+
   pub fn flat_slice(&self) -> Option<&[T]> {
     if !self.is_packed() {
       return None;
     }
     Some(&self.mem.as_slice()[self.flat_offset() .. self.flat_offset() + self.flat_size()])
   }
-}
 
-impl<'a, T> MemArrayView1d<'a, T> where T: Copy + 'static {
-  pub fn as_slice(&self) -> &[T] {
-    self.flat_slice().unwrap()
-  }
-
-  pub fn view<R>(self, r: R) -> MemArrayView<'a, usize, T>
-  where R: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_1d(r, self.size);
-    let view_size = end_idx - start_idx;
-    let view_offset = self.offset + start_idx;
+  /// Returns a view of the elements within one range per axis, without
+  /// copying; see `ViewRange`.
+  pub fn view<R>(self, r: R) -> MemArrayView<'a, Idx, T> where R: ViewRange<Idx> {
+    let (start_idx, end_idx) = r.to_bounds(&self.size);
+    let view_size = end_idx.index_sub(&start_idx);
+    let view_offset = elem_offset(self.offset, &start_idx, &self.stride);
     MemArrayView{
       size:     view_size,
       offset:   view_offset,
@@ -429,16 +1458,12 @@ impl<'a, T> MemArrayView1d<'a, T> where T: Copy + 'static {
       mem:      self.mem,
     }
   }
-}
 
-impl<'a, T> MemArrayView2d<'a, T> where T: Copy + 'static {
-  pub fn view<R0, R1>(self, r0: R0, r1: R1) -> MemArrayView<'a, [usize; 2], T>
-  where R0: RangeBounds<usize>,
-        R1: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_2d(r0, r1, self.size);
+  /// Like `view`, but with numpy-style signed ranges; see `SignedViewRange`.
+  pub fn view_signed<R>(self, r: R) -> MemArrayView<'a, Idx, T> where R: SignedViewRange<Idx> {
+    let (start_idx, end_idx) = r.to_bounds(&self.size);
     let view_size = end_idx.index_sub(&start_idx);
-    let view_offset = self.offset.index_add(&start_idx);
+    let view_offset = elem_offset(self.offset, &start_idx, &self.stride);
     MemArrayView{
       size:     view_size,
       offset:   view_offset,
@@ -446,52 +1471,140 @@ impl<'a, T> MemArrayView2d<'a, T> where T: Copy + 'static {
       mem:      self.mem,
     }
   }
-}
 
-impl<'a, T> MemArrayView3d<'a, T> where T: Copy + 'static {
-  pub fn view<R0, R1, R2>(self, r0: R0, r1: R1, r2: R2) -> MemArrayView<'a, [usize; 3], T>
-  where R0: RangeBounds<usize>,
-        R1: RangeBounds<usize>,
-        R2: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_3d(r0, r1, r2, self.size);
-    let view_size = end_idx.index_sub(&start_idx);
-    let view_offset = self.offset.index_add(&start_idx);
+  /// Slices each axis according to `spec`, without copying. Ranges may be
+  /// stepped and reversed; axes selected by a single index are dropped, so
+  /// `NewIdx` has one axis per range in `spec`.
+  pub fn slice<NewIdx>(self, spec: &[SliceSpec]) -> MemArrayView<'a, NewIdx, T> where NewIdx: ArrayIndex {
+    let (size, stride, offset) = slice_layout(&self.size.to_nd(), &self.stride.to_nd(), self.offset, spec);
+    assert_eq!(size.len(), NewIdx::zero().to_nd().len(),
+        "MemArrayView::slice: rank mismatch: spec: {:?} new size: {:?}", spec, size);
     MemArrayView{
-      size:     view_size,
-      offset:   view_offset,
-      stride:   self.stride,
+      size:     NewIdx::from_nd(size),
+      offset:   offset,
+      stride:   NewIdx::from_nd(stride),
       mem:      self.mem,
     }
   }
-}
 
-impl<'a, T> MemArrayView4d<'a, T> where T: Copy + 'static {
-  pub fn view<R0, R1, R2, R3>(self, r0: R0, r1: R1, r2: R2, r3: R3) -> MemArrayView<'a, [usize; 4], T>
-  where R0: RangeBounds<usize>,
-        R1: RangeBounds<usize>,
-        R2: RangeBounds<usize>,
-        R3: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_4d(r0, r1, r2, r3, self.size);
-    let view_size = end_idx.index_sub(&start_idx);
-    let view_offset = self.offset.index_add(&start_idx);
+  /// Returns a view of the same elements with a new size, which may have a
+  /// different rank, without copying. Fails if the view's layout does not
+  /// allow this; see `reshape_copy`.
+  pub fn reshape<NewIdx>(self, new_size: NewIdx) -> Result<MemArrayView<'a, NewIdx, T>, ()> where NewIdx: ArrayIndex {
+    let new_stride = match reshape_stride(&self.size.to_nd(), &self.stride.to_nd(), &new_size.to_nd()) {
+      None => return Err(()),
+      Some(new_stride) => NewIdx::from_nd(new_stride),
+    };
+    Ok(MemArrayView{
+      size:     new_size,
+      offset:   self.offset,
+      stride:   new_stride,
+      mem:      self.mem,
+    })
+  }
+
+  /// Returns a view whose axis `d` is axis `perm[d]` of this view, without
+  /// copying.
+  pub fn permute_axes(self, perm: Idx) -> MemArrayView<'a, Idx, T> {
+    let (size, stride) = permute_layout(&self.size, &self.stride, &perm.to_nd());
     MemArrayView{
-      size:     view_size,
-      offset:   view_offset,
-      stride:   self.stride.clone(),
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      mem:      self.mem,
+    }
+  }
+
+  pub fn swap_axes(self, a: usize, b: usize) -> MemArrayView<'a, Idx, T> {
+    let perm = swap_perm(self.size.to_nd().len(), a, b);
+    let (size, stride) = permute_layout(&self.size, &self.stride, &perm);
+    MemArrayView{
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
       mem:      self.mem,
     }
   }
+
+  /// Returns a view of this view's elements repeated to size `new_size`,
+  /// without copying; see `broadcast_stride` for the rules. Fails if the
+  /// sizes are incompatible.
+  pub fn broadcast_to<NewIdx>(self, new_size: NewIdx) -> Result<MemArrayView<'a, NewIdx, T>, ()> where NewIdx: ArrayIndex {
+    let new_stride = match broadcast_stride(&self.size.to_nd(), &self.stride.to_nd(), &new_size.to_nd()) {
+      None => return Err(()),
+      Some(new_stride) => NewIdx::from_nd(new_stride),
+    };
+    Ok(MemArrayView{
+      size:     new_size,
+      offset:   self.offset,
+      stride:   new_stride,
+      mem:      self.mem,
+    })
+  }
+
+  /// Copies the view's elements into a new packed array of the same size.
+  pub fn to_packed(&self) -> MemArray<Idx, T> where T: ZeroBits {
+    self.reshape_copy(self.size.clone())
+  }
+
+  /// Copies the view's elements, in column-major order, into a new packed
+  /// array of size `new_size`.
+  pub fn reshape_copy<NewIdx>(&self, new_size: NewIdx) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex, T: ZeroBits {
+    assert_eq!(self.flat_size(), new_size.flat_len(),
+        "MemArrayView::reshape_copy: size mismatch: old size: {:?} new size: {:?}", self.size, new_size);
+    let mut dst = MemArray::zeros(new_size);
+    {
+      let src = self.mem.as_slice();
+      let dst = dst.memory_mut().as_mut_slice();
+      let mut i = 0;
+      for_each_flat_offset(&self.size.to_nd(), &self.stride.to_nd(), self.offset, |off| {
+        dst[i] = src[off];
+        i += 1;
+      });
+    }
+    dst
+  }
+}
+
+impl<'a, Idx, T> Index<Idx> for MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  type Output = T;
+
+  fn index(&self, idx: Idx) -> &T {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => panic!("MemArrayView: index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(off) => &self.mem.as_slice()[off],
+    }
+  }
+}
+
+impl<'a, T> MemArrayView1d<'a, T> where T: Copy + 'static {
+  pub fn as_slice(&self) -> &[T] {
+    self.flat_slice().unwrap()
+  }
+}
+
+impl<'a, T> MemArrayView2d<'a, T> where T: Copy + 'static {
+  pub fn transpose(self) -> MemArrayView2d<'a, T> {
+    self.permute_axes([1, 0])
+  }
 }
 
 pub struct MemArrayViewMut<'a, Idx, T> where T: Copy + 'static {
   size:     Idx,
-  offset:   Idx,
+  offset:   usize,
   stride:   Idx,
-  mem:      &'a mut Mem<T>,
+  // Base and length of the underlying memory. Views split from one another
+  // (see `split_at_mut`) share the memory but address disjoint elements, so
+  // elements are only ever accessed one at a time through the pointer, never
+  // through a slice of the whole memory.
+  ptr:      *mut T,
+  mem_len:  usize,
+  _mrk:     PhantomData<&'a mut [T]>,
 }
 
+unsafe impl<'a, Idx, T> Send for MemArrayViewMut<'a, Idx, T> where Idx: Send, T: Copy + Send + 'static {}
+unsafe impl<'a, Idx, T> Sync for MemArrayViewMut<'a, Idx, T> where Idx: Sync, T: Copy + Sync + 'static {}
+
 pub type MemArrayViewMut0d<'a, T> = MemArrayViewMut<'a, Index0d, T>;
 pub type MemArrayViewMut1d<'a, T> = MemArrayViewMut<'a, Index1d, T>;
 pub type MemArrayViewMut2d<'a, T> = MemArrayViewMut<'a, Index2d, T>;
@@ -516,8 +1629,8 @@ impl<'a, Idx, T> Array for MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T:
 }
 
 impl<'a, Idx, T> DenseArray for MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
-  fn offset(&self) -> Idx {
-    self.offset.clone()
+  fn flat_offset(&self) -> usize {
+    self.offset
   }
 
   fn stride(&self) -> Idx {
@@ -526,19 +1639,104 @@ impl<'a, Idx, T> DenseArray for MemArrayViewMut<'a, Idx, T> where Idx: ArrayInde
 }
 
 impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  fn new<M>(size: Idx, offset: usize, stride: Idx, mem: &'a mut M) -> Self where M: Mem<T> + ?Sized {
+    let mem_len = mem.as_slice().len();
+    unsafe { MemArrayViewMut::from_raw(size, offset, stride, mem.as_mut_ptr(), mem_len) }
+  }
+
+  // The caller must ensure that the `mem_len` elements at `ptr` stay valid
+  // and are not otherwise accessed for the lifetime `'a`.
+  unsafe fn from_raw(size: Idx, offset: usize, stride: Idx, ptr: *mut T, mem_len: usize) -> Self {
+    MemArrayViewMut{
+      size:     size,
+      offset:   offset,
+      stride:   stride,
+      ptr:      ptr,
+      mem_len:  mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+
+  fn elem(&self, off: usize) -> &T {
+    assert!(off < self.mem_len);
+    unsafe { &*self.ptr.offset(off as isize) }
+  }
+
+  fn elem_mut(&mut self, off: usize) -> &mut T {
+    assert!(off < self.mem_len);
+    unsafe { &mut *self.ptr.offset(off as isize) }
+  }
+
   pub unsafe fn as_ptr(&self) -> *const T {
-    self.mem.as_ptr().offset(self.flat_offset() as _)
+    self.ptr.offset(self.flat_offset() as _)
   }
 
   pub unsafe fn as_mut_ptr(&mut self) -> *mut T {
-    self.mem.as_mut_ptr().offset(self.flat_offset() as _)
+    self.ptr.offset(self.flat_offset() as _)
+  }
+
+  pub fn get(&self, idx: Idx) -> Option<&T> {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => None,
+      Some(off) => Some(self.elem(off)),
+    }
+  }
+
+  pub unsafe fn get_unchecked(&self, idx: Idx) -> &T {
+    &*self.ptr.offset(elem_offset(self.offset, &idx, &self.stride) as isize)
+  }
+
+  pub fn get_signed<I>(&self, idx: I) -> Option<&T> where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => None,
+      Some(idx) => Some(self.elem(elem_offset(self.offset, &idx, &self.stride))),
+    }
+  }
+
+  pub fn get_mut(&mut self, idx: Idx) -> Option<&mut T> {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => None,
+      Some(off) => Some(self.elem_mut(off)),
+    }
+  }
+
+  pub unsafe fn get_unchecked_mut(&mut self, idx: Idx) -> &mut T {
+    &mut *self.ptr.offset(elem_offset(self.offset, &idx, &self.stride) as isize)
+  }
+
+  pub fn get_signed_mut<I>(&mut self, idx: I) -> Option<&mut T> where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => None,
+      Some(idx) => {
+        let off = elem_offset(self.offset, &idx, &self.stride);
+        Some(self.elem_mut(off))
+      }
+    }
+  }
+
+  pub fn set_signed<I>(&mut self, idx: I, value: T) where I: SignedIndex<Idx=Idx> {
+    match idx.resolve(&self.size) {
+      None => panic!("index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(idx) => self[idx] = value,
+    }
+  }
+
+  pub fn set(&mut self, idx: Idx, value: T) {
+    self[idx] = value;
+  }
+
+  pub unsafe fn set_unchecked(&mut self, idx: Idx, value: T) {
+    *self.get_unchecked_mut(idx) = value;
   }
 
   pub fn flat_slice(&self) -> Option<&[T]> {
     if !self.is_packed() {
       return None;
     }
-    Some(&self.mem.as_slice()[self.flat_offset() .. self.flat_offset() + self.flat_size()])
+    let off = self.flat_offset();
+    let len = self.flat_size();
+    assert!(off + len <= self.mem_len);
+    Some(unsafe { from_raw_parts(self.ptr.offset(off as isize), len) })
   }
 
   pub fn flat_slice_mut(&mut self) -> Option<&mut [T]> {
@@ -547,84 +1745,634 @@ impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 's
     }
     let off = self.flat_offset();
     let len = self.flat_size();
-    Some(&mut self.mem.as_mut_slice()[off .. off + len])
+    assert!(off + len <= self.mem_len);
+    Some(unsafe { from_raw_parts_mut(self.ptr.offset(off as isize), len) })
   }
-}
 
-impl<'a, T> MemArrayViewMut1d<'a, T> where T: Copy + 'static {
-  pub fn as_slice(&self) -> &[T] {
-    self.flat_slice().unwrap()
-  }
-
-  pub fn as_mut_slice(&mut self) -> &mut [T] {
-    self.flat_slice_mut().unwrap()
-  }
-
-  pub fn view_mut<R>(self, r: R) -> MemArrayViewMut<'a, usize, T>
-  where R: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_1d(r, self.size);
-    let view_size = end_idx - start_idx;
-    let view_offset = self.offset + start_idx;
+  pub fn view_mut<R>(self, r: R) -> MemArrayViewMut<'a, Idx, T> where R: ViewRange<Idx> {
+    let (start_idx, end_idx) = r.to_bounds(&self.size);
+    let view_size = end_idx.index_sub(&start_idx);
+    let view_offset = elem_offset(self.offset, &start_idx, &self.stride);
     MemArrayViewMut{
       size:     view_size,
       offset:   view_offset,
       stride:   self.stride,
-      mem:      self.mem,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
     }
   }
-}
 
-impl<'a, T> MemArrayViewMut2d<'a, T> where T: Copy + 'static {
-  pub fn view_mut<R0, R1>(self, r0: R0, r1: R1) -> MemArrayViewMut<'a, [usize; 2], T>
-  where R0: RangeBounds<usize>,
-        R1: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_2d(r0, r1, self.size);
+  pub fn view_mut_signed<R>(self, r: R) -> MemArrayViewMut<'a, Idx, T> where R: SignedViewRange<Idx> {
+    let (start_idx, end_idx) = r.to_bounds(&self.size);
     let view_size = end_idx.index_sub(&start_idx);
-    let view_offset = self.offset.index_add(&start_idx);
+    let view_offset = elem_offset(self.offset, &start_idx, &self.stride);
     MemArrayViewMut{
       size:     view_size,
       offset:   view_offset,
       stride:   self.stride,
-      mem:      self.mem,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
     }
   }
-}
 
-impl<'a, T> MemArrayViewMut3d<'a, T> where T: Copy + 'static {
-  pub fn view_mut<R0, R1, R2>(self, r0: R0, r1: R1, r2: R2) -> MemArrayViewMut<'a, [usize; 3], T>
-  where R0: RangeBounds<usize>,
-        R1: RangeBounds<usize>,
-        R2: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_3d(r0, r1, r2, self.size);
-    let view_size = end_idx.index_sub(&start_idx);
-    let view_offset = self.offset.index_add(&start_idx);
+  /// Like `MemArrayView::slice`.
+  pub fn slice_mut<NewIdx>(self, spec: &[SliceSpec]) -> MemArrayViewMut<'a, NewIdx, T> where NewIdx: ArrayIndex {
+    let (size, stride, offset) = slice_layout(&self.size.to_nd(), &self.stride.to_nd(), self.offset, spec);
+    assert_eq!(size.len(), NewIdx::zero().to_nd().len(),
+        "MemArrayViewMut::slice_mut: rank mismatch: spec: {:?} new size: {:?}", spec, size);
     MemArrayViewMut{
-      size:     view_size,
-      offset:   view_offset,
-      stride:   self.stride,
-      mem:      self.mem,
+      size:     NewIdx::from_nd(size),
+      offset:   offset,
+      stride:   NewIdx::from_nd(stride),
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
     }
   }
-}
 
-impl<'a, T> MemArrayViewMut4d<'a, T> where T: Copy + 'static {
-  pub fn view_mut<R0, R1, R2, R3>(self, r0: R0, r1: R1, r2: R2, r3: R3) -> MemArrayViewMut<'a, [usize; 4], T>
-  where R0: RangeBounds<usize>,
-        R1: RangeBounds<usize>,
-        R2: RangeBounds<usize>,
-        R3: RangeBounds<usize>,
-  {
-    let (start_idx, end_idx) = range2idxs_4d(r0, r1, r2, r3, self.size);
-    let view_size = end_idx.index_sub(&start_idx);
-    let view_offset = self.offset.index_add(&start_idx);
+  /// Returns a mutable view of the same elements with a new size, which may
+  /// have a different rank, without copying. Fails if the view's layout does
+  /// not allow this.
+  pub fn reshape_mut<NewIdx>(self, new_size: NewIdx) -> Result<MemArrayViewMut<'a, NewIdx, T>, ()> where NewIdx: ArrayIndex {
+    let new_stride = match reshape_stride(&self.size.to_nd(), &self.stride.to_nd(), &new_size.to_nd()) {
+      None => return Err(()),
+      Some(new_stride) => NewIdx::from_nd(new_stride),
+    };
+    Ok(MemArrayViewMut{
+      size:     new_size,
+      offset:   self.offset,
+      stride:   new_stride,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    })
+  }
+
+  /// Returns a mutable view whose axis `d` is axis `perm[d]` of this view,
+  /// without copying.
+  pub fn permute_axes(self, perm: Idx) -> MemArrayViewMut<'a, Idx, T> {
+    let (size, stride) = permute_layout(&self.size, &self.stride, &perm.to_nd());
     MemArrayViewMut{
-      size:     view_size,
-      offset:   view_offset,
-      stride:   self.stride.clone(),
-      mem:      self.mem,
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+
+  pub fn swap_axes(self, a: usize, b: usize) -> MemArrayViewMut<'a, Idx, T> {
+    let perm = swap_perm(self.size.to_nd().len(), a, b);
+    let (size, stride) = permute_layout(&self.size, &self.stride, &perm);
+    MemArrayViewMut{
+      size:     size,
+      offset:   self.offset,
+      stride:   stride,
+      ptr:      self.ptr,
+      mem_len:  self.mem_len,
+      _mrk:     PhantomData,
+    }
+  }
+
+  /// Copies the view's elements into a new packed array of the same size.
+  pub fn to_packed(&self) -> MemArray<Idx, T> where T: ZeroBits {
+    let mut dst = MemArray::zeros(self.size.clone());
+    {
+      let dst = dst.memory_mut().as_mut_slice();
+      let mut i = 0;
+      for_each_flat_offset(&self.size.to_nd(), &self.stride.to_nd(), self.offset, |off| {
+        dst[i] = *self.elem(off);
+        i += 1;
+      });
+    }
+    dst
+  }
+}
+
+impl<'a, Idx, T> Index<Idx> for MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  type Output = T;
+
+  fn index(&self, idx: Idx) -> &T {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => panic!("MemArrayViewMut: index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(off) => self.elem(off),
+    }
+  }
+}
+
+impl<'a, Idx, T> IndexMut<Idx> for MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  fn index_mut(&mut self, idx: Idx) -> &mut T {
+    match checked_elem_offset(self.offset, &idx, &self.size, &self.stride) {
+      None => panic!("MemArrayViewMut: index out of bounds: index: {:?} size: {:?}", idx, self.size),
+      Some(off) => self.elem_mut(off),
+    }
+  }
+}
+
+impl<'a, T> MemArrayViewMut1d<'a, T> where T: Copy + 'static {
+  pub fn as_slice(&self) -> &[T] {
+    self.flat_slice().unwrap()
+  }
+
+  pub fn as_mut_slice(&mut self) -> &mut [T] {
+    self.flat_slice_mut().unwrap()
+  }
+}
+
+impl<'a, T> MemArrayViewMut2d<'a, T> where T: Copy + 'static {
+  pub fn transpose(self) -> MemArrayViewMut2d<'a, T> {
+    self.permute_axes([1, 0])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::cell::{Cell};
+  use std::rc::{Rc};
+
+  #[test]
+  fn srange2idx_negative() {
+    assert_eq!(srange2idx(.., 5), (0, 5));
+    assert_eq!(srange2idx(-2 .., 5), (3, 5));
+    assert_eq!(srange2idx(.. -1, 5), (0, 4));
+    assert_eq!(srange2idx(..= -1, 5), (0, 5));
+    assert_eq!(srange2idx(-3 ..= -2, 5), (2, 4));
+  }
+
+  #[test]
+  fn srange2idx_out_of_range() {
+    assert_eq!(srange2idx(..= -10, 5), (0, 0));
+    assert_eq!(srange2idx(..= -6, 5), (0, 0));
+    assert_eq!(srange2idx(.. -10, 5), (0, 0));
+    assert_eq!(srange2idx(-10 .., 5), (0, 5));
+    assert_eq!(srange2idx((Bound::Excluded(-10), Bound::Unbounded), 5), (0, 5));
+    assert_eq!(srange2idx((Bound::Excluded(-6), Bound::Unbounded), 5), (0, 5));
+    assert_eq!(srange2idx((Bound::Excluded(-5), Bound::Unbounded), 5), (1, 5));
+    assert_eq!(srange2idx(3 .. 10, 5), (3, 5));
+    assert_eq!(srange2idx(..= isize::max_value(), 5), (0, 5));
+    assert_eq!(srange2idx((Bound::Excluded(isize::max_value()), Bound::Unbounded), 5), (5, 5));
+    assert_eq!(srange2idx(4 .. 2, 5), (4, 4));
+  }
+
+  #[test]
+  fn view_signed_out_of_range() {
+    let a = MemArray2d::<f32>::zeros([5, 3]);
+    assert_eq!(a.view_signed((..= -10, ..)).size(), [0, 3]);
+    assert_eq!(a.view_signed(((Bound::Excluded(-10), Bound::Unbounded), -2 ..)).size(), [5, 2]);
+    assert_eq!(a.view_signed((-2 ..= -1, ..= -3)).size(), [2, 1]);
+  }
+
+  #[test]
+  fn range2idx_in_bounds() {
+    assert_eq!(range2idx(1 ..= 3, 0, 5), (1, 4));
+    assert_eq!(range2idx((Bound::Excluded(1), Bound::Unbounded), 0, 5), (2, 5));
+    assert_eq!(range2idx(.., 0, 0), (0, 0));
+  }
+
+  #[test]
+  #[should_panic(expected = "view: range out of bounds")]
+  fn range2idx_inclusive_overflow() {
+    range2idx(0 ..= usize::max_value(), 0, 5);
+  }
+
+  #[test]
+  #[should_panic(expected = "view: range out of bounds")]
+  fn range2idx_exclusive_overflow() {
+    range2idx((Bound::Excluded(usize::max_value()), Bound::Unbounded), 0, 5);
+  }
+
+  #[test]
+  fn broadcast_stride_rules() {
+    // Equal axes keep their stride; length-1 and missing axes get zero.
+    assert_eq!(broadcast_stride(&[3, 4], &[1, 3], &[3, 4]), Some(vec![1, 3]));
+    assert_eq!(broadcast_stride(&[3, 1], &[1, 3], &[3, 4]), Some(vec![1, 0]));
+    assert_eq!(broadcast_stride(&[1, 4], &[1, 1], &[3, 4, 2]), Some(vec![0, 1, 0]));
+    assert_eq!(broadcast_stride(&[3], &[2], &[3, 5]), Some(vec![2, 0]));
+    assert_eq!(broadcast_stride(&[], &[], &[2, 2]), Some(vec![0, 0]));
+    assert_eq!(broadcast_stride(&[3], &[1], &[0]), None);
+    assert_eq!(broadcast_stride(&[3], &[1], &[4]), None);
+    assert_eq!(broadcast_stride(&[3, 2], &[1, 3], &[3]), None);
+    assert_eq!(broadcast_stride(&[3, 2], &[1, 3], &[3, 1]), None);
+  }
+
+  #[test]
+  fn broadcast_view_is_not_packed() {
+    let mut a = MemArray1d::<f32>::zeros(3);
+    a.set(2, 7.0);
+    let v = a.broadcast_to([3, 2]).unwrap();
+    assert!(!v.is_packed());
+    assert!(v.flat_slice().is_none());
+    assert_eq!(v[[2, 1]], 7.0);
+    assert_eq!(v.to_packed()[[2, 0]], 7.0);
+    let w = a.broadcast_to(3).unwrap();
+    assert!(w.is_packed());
+    let one = MemArray1d::<f32>::zeros(1);
+    assert!(one.broadcast_to([1, 1]).unwrap().is_packed());
+    assert!(a.broadcast_to([2, 3]).is_err());
+  }
+
+  #[cfg(feature = "guard")]
+  #[test]
+  #[should_panic(expected = "guard bytes after the buffer were overwritten: len: 6 bytes: 24 array: size: [2, 3] stride: [1, 2]")]
+  fn guard_desc_after_reshape() {
+    let a = MemArray1d::<f32>::zeros(6).reshape_into([2, 3]);
+    let mut a = a.into_cow();
+    unsafe { *a.memory_mut().as_mut_ptr().offset(6) = 1.0 };
+  }
+
+  #[cfg(feature = "guard")]
+  #[test]
+  #[should_panic(expected = "guard bytes before the buffer were overwritten: len: 4 bytes: 4 array: size: 4 stride: 1")]
+  fn guard_desc_with_memory() {
+    let mem = unsafe { HeapMem::<u8>::alloc(4) };
+    let mut a = MemArray1d::with_memory(4, mem);
+    unsafe { *a.memory_mut().as_mut_ptr().offset(-1) = 0 };
+  }
+
+  #[test]
+  fn reshape_stride_packed() {
+    assert_eq!(reshape_stride(&[2, 3, 4], &[1, 2, 6], &[6, 4]), Some(vec![1, 6]));
+    assert_eq!(reshape_stride(&[2, 3, 4], &[1, 2, 6], &[24]), Some(vec![1]));
+    assert_eq!(reshape_stride(&[2, 3, 4], &[1, 2, 6], &[2, 12]), Some(vec![1, 2]));
+    assert_eq!(reshape_stride(&[6], &[2], &[6, 1]), Some(vec![2, 12]));
+  }
+
+  #[test]
+  fn reshape_stride_unit_axes() {
+    // The stride of a length-1 axis is arbitrary and must be ignored.
+    assert_eq!(reshape_stride(&[1, 6], &[99, 1], &[6]), Some(vec![1]));
+    assert_eq!(reshape_stride(&[1, 6], &[99, 1], &[2, 3]), Some(vec![1, 2]));
+    assert_eq!(reshape_stride(&[2, 3], &[1, 4], &[1, 2, 3]), Some(vec![1, 1, 4]));
+    assert_eq!(reshape_stride(&[2, 3], &[1, 4], &[2, 3, 1]), Some(vec![1, 4, 12]));
+  }
+
+  #[test]
+  fn reshape_stride_strided() {
+    // Two rows of a 4 x 3 array cannot be merged into one axis.
+    assert_eq!(reshape_stride(&[2, 3], &[1, 4], &[6]), None);
+    assert_eq!(reshape_stride(&[2, 3], &[1, 4], &[3, 2]), None);
+    // But a gap between the merged axes and the rest is fine.
+    assert_eq!(reshape_stride(&[2, 3, 4], &[1, 2, 8], &[6, 4]), Some(vec![1, 8]));
+    assert_eq!(reshape_stride(&[2, 3, 4], &[1, 2, 8], &[2, 12]), None);
+  }
+
+  #[test]
+  fn reshape_stride_reversed() {
+    let neg = |s: usize| s.wrapping_neg();
+    assert_eq!(reshape_stride(&[3], &[neg(1)], &[3, 1]), Some(vec![neg(1), neg(3)]));
+    assert_eq!(reshape_stride(&[6], &[neg(1)], &[2, 3]), Some(vec![neg(1), neg(2)]));
+  }
+
+  #[test]
+  fn reshape_stride_empty() {
+    assert_eq!(reshape_stride(&[0, 3], &[1, 4], &[3, 0]), Some(vec![1, 3]));
+  }
+
+  #[test]
+  #[should_panic(expected = "reshape: size mismatch")]
+  fn reshape_stride_size_mismatch() {
+    reshape_stride(&[2, 3], &[1, 2], &[7]);
+  }
+
+  #[test]
+  fn reshape_views() {
+    let mut a = MemArray2d::<f32>::zeros([4, 3]);
+    for j in 0 .. 3 {
+      for i in 0 .. 4 {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
+    }
+    let flat = a.as_view().reshape(12).unwrap();
+    assert_eq!(flat[5], 11.0);
+    let rows = a.as_view().view((0 .. 2, ..));
+    assert!(rows.clone().reshape(6).is_err());
+    let copy = rows.reshape_copy(6);
+    assert_eq!(copy.as_view().flat_slice().unwrap(), &[0.0, 1.0, 10.0, 11.0, 20.0, 21.0]);
+    let a = a.reshape_into([2, 6]);
+    assert_eq!(a.stride, [1, 2]);
+    assert_eq!(a[[1, 5]], 23.0);
+  }
+
+  #[test]
+  fn slice_layout_steps() {
+    let spec = [SliceSpec::from(2 .. 8).step(3)];
+    assert_eq!(slice_layout(&[10], &[1], 0, &spec), (vec![2], vec![3], 2));
+    let spec = [SliceSpec::from(-3 ..)];
+    assert_eq!(slice_layout(&[5], &[2], 1, &spec), (vec![3], vec![2], 5));
+    let spec = [SliceSpec::from(..=-2).step(2)];
+    assert_eq!(slice_layout(&[5], &[1], 0, &spec), (vec![2], vec![2], 0));
+  }
+
+  #[test]
+  fn slice_layout_huge_step() {
+    let spec = [SliceSpec::all().step(usize::max_value())];
+    assert_eq!(slice_layout(&[10], &[1], 0, &spec).0, vec![1]);
+    let spec = [SliceSpec::from(3 ..).step(usize::max_value() - 1).rev()];
+    assert_eq!(slice_layout(&[10], &[1], 0, &spec), (vec![1], vec![2], 3));
+    let spec = [SliceSpec::from(5 .. 5).step(usize::max_value())];
+    assert_eq!(slice_layout(&[10], &[1], 0, &spec).0, vec![0]);
+  }
+
+  #[test]
+  #[should_panic(expected = "SliceSpec: step overflow")]
+  fn slice_step_overflow() {
+    SliceSpec::all().step(1 << 40).step(1 << 40);
+  }
+
+  #[test]
+  fn slice_layout_reversed() {
+    let neg = |s: usize| s.wrapping_neg();
+    let spec = [SliceSpec::all().rev()];
+    assert_eq!(slice_layout(&[5], &[1], 0, &spec), (vec![5], vec![neg(1)], 4));
+    // Reversal keeps the elements selected by the step: 1, 4, 7.
+    let spec = [SliceSpec::from(1 .. 9).step(3).rev()];
+    assert_eq!(slice_layout(&[10], &[1], 0, &spec), (vec![3], vec![neg(3)], 7));
+    let spec = [SliceSpec::from(3 .. 3).rev()];
+    assert_eq!(slice_layout(&[5], &[1], 0, &spec), (vec![0], vec![neg(1)], 3));
+    let spec = [SliceSpec::all().rev().rev()];
+    assert_eq!(slice_layout(&[5], &[1], 0, &spec), (vec![5], vec![1], 0));
+  }
+
+  #[test]
+  fn slice_layout_index() {
+    let spec = [SliceSpec::from(-1), SliceSpec::from(1 ..)];
+    assert_eq!(slice_layout(&[3, 4], &[1, 3], 0, &spec), (vec![3], vec![3], 5));
+    let spec = [SliceSpec::from(..), SliceSpec::from(0)];
+    assert_eq!(slice_layout(&[3, 4], &[1, 3], 0, &spec), (vec![3], vec![1], 0));
+  }
+
+  #[test]
+  #[should_panic(expected = "slice: index out of bounds: index: -4 axis: 0 len: 3")]
+  fn slice_layout_index_out_of_bounds() {
+    slice_layout(&[3], &[1], 0, &[SliceSpec::from(-4)]);
+  }
+
+  #[test]
+  fn slice_views() {
+    let mut a = MemArray2d::<f32>::zeros([3, 4]);
+    for j in 0 .. 4 {
+      for i in 0 .. 3 {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
+    }
+    let row = a.as_view().slice::<Index1d>(&[SliceSpec::from(-1), SliceSpec::all().rev()]);
+    assert_eq!(row.size, 4);
+    assert!(!row.is_packed());
+    assert_eq!(row.to_packed().as_view().flat_slice().unwrap(), &[32.0, 22.0, 12.0, 2.0]);
+    let b = a.as_view().slice::<Index2d>(&[SliceSpec::all().step(2).rev(), SliceSpec::from(1 .. -1)]);
+    assert_eq!(b.size, [2, 2]);
+    assert_eq!(b[[0, 0]], 12.0);
+    assert_eq!(b[[1, 0]], 10.0);
+    assert_eq!(b[[0, 1]], 22.0);
+    assert_eq!(b[[1, 1]], 20.0);
+  }
+
+  #[test]
+  #[allow(deprecated)]
+  fn dense_offset_index() {
+    let a = MemArray2d::<f32>::zeros([4, 3]);
+    assert_eq!(a.offset(), [0, 0]);
+    let v = a.as_view().view((1 .., 2 ..));
+    assert_eq!(v.flat_offset(), 9);
+    assert_eq!(v.offset(), [1, 2]);
+  }
+
+  #[test]
+  #[allow(deprecated)]
+  #[should_panic(expected = "DenseArray::offset: offset is not an index")]
+  fn dense_offset_reversed() {
+    let a = MemArray2d::<f32>::zeros([4, 3]);
+    let r = a.as_view().slice::<Index1d>(&[SliceSpec::all().rev(), SliceSpec::from(1)]);
+    assert_eq!(r.flat_offset(), 7);
+    r.offset();
+  }
+
+  #[test]
+  fn arc_share_and_unique() {
+    let mut a = MemArray1d::<f32>::zeros(3).into_shared();
+    assert!(a.is_unique());
+    a.try_unique_mut().unwrap().set(1, 5.0);
+    let b = a.share();
+    assert!(!a.is_unique());
+    assert!(!b.is_unique());
+    assert!(a.try_unique_mut().is_none());
+    assert_eq!(b[1], 5.0);
+    let a = match a.try_into_unique() {
+      Err(a) => a,
+      Ok(_) => panic!("try_into_unique: memory is shared"),
+    };
+    drop(b);
+    assert!(a.is_unique());
+    let u = a.try_into_unique().ok().unwrap();
+    assert_eq!(u.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 5.0, 0.0]);
+  }
+
+  #[test]
+  #[should_panic(expected = "ArcMem: mutable access to memory that is shared")]
+  fn arc_shared_mut() {
+    let mut a = MemArray1d::<f32>::zeros(3).into_shared();
+    let _b = a.share();
+    a.as_view_mut();
+  }
+
+  #[test]
+  fn cow_copies_once_on_first_write() {
+    let mut a = MemArray1d::<f32>::zeros(3);
+    a.set(0, 1.0);
+    let src = a.into_cow();
+    let mut b = src.clone();
+    assert!(src.memory().is_shared());
+    assert_eq!(src.memory().as_slice().as_ptr(), b.memory().as_slice().as_ptr());
+    b.set(1, 2.0);
+    assert!(!src.memory().is_shared());
+    assert!(!b.memory().is_shared());
+    let copy_ptr = b.memory().as_slice().as_ptr();
+    assert!(copy_ptr != src.memory().as_slice().as_ptr());
+    // Further writes go to the private copy.
+    b.set(2, 3.0);
+    assert_eq!(b.memory().as_slice().as_ptr(), copy_ptr);
+    assert_eq!(b.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0, 2.0, 3.0]);
+    assert_eq!(src.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0, 0.0, 0.0]);
+  }
+
+  #[test]
+  fn cow_unshared_writes_in_place() {
+    let mut a = MemArray1d::<f32>::zeros(3).into_cow();
+    let ptr = a.memory().as_slice().as_ptr();
+    a.set(0, 1.0);
+    assert_eq!(a.memory().as_slice().as_ptr(), ptr);
+    assert_eq!(a[0], 1.0);
+  }
+
+  #[test]
+  fn view_rank0() {
+    let mut a = MemArray0d::<f32>::zeros(());
+    a.set((), 4.0);
+    let v = a.as_view().view(());
+    assert_eq!(v.size, ());
+    assert_eq!(v[()], 4.0);
+    a.view_mut(()).set((), 5.0);
+    assert_eq!(a[()], 5.0);
+  }
+
+  #[test]
+  fn view_rank5() {
+    let mut a = MemArray5d::<f32>::zeros([2, 3, 2, 2, 3]);
+    a.set([1, 2, 1, 0, 2], 7.0);
+    let v = a.as_view().view((1 .., 1 ..= 2, .., .. 1, 2 ..));
+    assert_eq!(v.size, [1, 2, 2, 1, 1]);
+    assert_eq!(v[[0, 1, 1, 0, 0]], 7.0);
+    assert_eq!(v.iter().filter(|&&x| x == 7.0).count(), 1);
+    a.view_mut((.., .., .., 1 .., ..)).set([0, 0, 0, 0, 0], 1.0);
+    assert_eq!(a[[0, 0, 0, 1, 0]], 1.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "view: range out of bounds: axis: 4")]
+  fn view_rank5_out_of_bounds() {
+    let a = MemArray5d::<f32>::zeros([2, 3, 2, 2, 3]);
+    a.view((.., .., .., .., 1 .. 4));
+  }
+
+  fn iota2(size: [usize; 2]) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
     }
+    a
+  }
+
+  #[test]
+  fn get_out_of_bounds() {
+    let mut a = iota2([3, 2]);
+    assert_eq!(a.get([2, 1]), Some(&12.0));
+    assert_eq!(a.get([3, 0]), None);
+    assert_eq!(a.get([0, 2]), None);
+    assert!(a.get_mut([3, 1]).is_none());
+    let v = a.as_view().view((1 .., ..));
+    assert_eq!(v.get([1, 1]), Some(&12.0));
+    assert_eq!(v.get([2, 0]), None);
+    let mut w = a.as_view_mut().view_mut((.., 1 ..));
+    assert_eq!(w.get([0, 0]), Some(&10.0));
+    assert!(w.get_mut([0, 1]).is_none());
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArray: index out of bounds: index: [3, 0] size: [3, 2]")]
+  fn index_out_of_bounds() {
+    let a = iota2([3, 2]);
+    let _ = a[[3, 0]];
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArray: index out of bounds: index: [0, 2] size: [3, 2]")]
+  fn set_out_of_bounds() {
+    let mut a = iota2([3, 2]);
+    a.set([0, 2], 1.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayView: index out of bounds: index: [2, 0] size: [2, 2]")]
+  fn view_index_out_of_bounds() {
+    let a = iota2([3, 2]);
+    let v = a.as_view().view((1 .., ..));
+    let _ = v[[2, 0]];
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayViewMut: index out of bounds: index: [0, 1] size: [3, 1]")]
+  fn view_mut_set_out_of_bounds() {
+    let mut a = iota2([3, 2]);
+    let mut v = a.as_view_mut().view_mut((.., 1 ..));
+    v.set([0, 1], 1.0);
+  }
+
+  #[test]
+  fn strided_access() {
+    let mut a = iota2([4, 3]);
+    {
+      let v = a.as_view().view((1 .. 3, 1 ..));
+      assert_eq!(v[[1, 1]], 22.0);
+      assert_eq!(unsafe { *v.get_unchecked([0, 1]) }, 21.0);
+      let r = a.as_view().slice::<Index2d>(&[SliceSpec::all().rev(), SliceSpec::all()]);
+      assert_eq!(r[[0, 2]], 23.0);
+      assert_eq!(unsafe { *r.get_unchecked([3, 1]) }, 10.0);
+    }
+    {
+      let mut w = a.as_view_mut().view_mut((1 .. 3, 1 ..));
+      w[[0, 0]] = -1.0;
+      w.set([1, 1], -2.0);
+      unsafe {
+        w.set_unchecked([1, 0], -3.0);
+        *w.get_unchecked_mut([0, 1]) += 100.0;
+      }
+      assert_eq!(unsafe { *w.get_unchecked([1, 0]) }, -3.0);
+    }
+    assert_eq!(a[[1, 1]], -1.0);
+    assert_eq!(a[[2, 2]], -2.0);
+    assert_eq!(a[[2, 1]], -3.0);
+    assert_eq!(a[[1, 2]], 121.0);
+    unsafe {
+      a.set_unchecked([3, 2], 0.5);
+      assert_eq!(*a.get_unchecked([3, 2]), 0.5);
+    }
+    assert_eq!(a.as_view().iter().filter(|&&x| x < 0.0).count(), 3);
+  }
+
+  // Counts allocations and frees, and the bytes still outstanding.
+  #[derive(Clone, Default)]
+  struct CountingAlloc {
+    allocs: Rc<Cell<usize>>,
+    frees:  Rc<Cell<usize>>,
+    live:   Rc<Cell<usize>>,
+  }
+
+  unsafe impl Alloc for CountingAlloc {
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+      self.allocs.set(self.allocs.get() + 1);
+      self.live.set(self.live.get() + layout.size());
+      Global.alloc(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+      self.frees.set(self.frees.get() + 1);
+      self.live.set(self.live.get() - layout.size());
+      Global.dealloc(ptr, layout)
+    }
+  }
+
+  #[test]
+  fn zeros_in_routes_through_alloc() {
+    let counts = CountingAlloc::default();
+    {
+      let mut a = MemArray::<Index2d, f32, HeapMem<f32, CountingAlloc>>::zeros_in([3, 4], counts.clone());
+      assert_eq!(counts.allocs.get(), 1);
+      assert_eq!(counts.frees.get(), 0);
+      assert!(counts.live.get() >= 12 * size_of::<f32>());
+      assert!(a.as_view().iter().all(|&x| x == 0.0));
+      a.set([2, 3], 1.0);
+      assert_eq!(a.memory().allocator().allocs.get(), 1);
+    }
+    assert_eq!(counts.allocs.get(), 1);
+    assert_eq!(counts.frees.get(), 1);
+    assert_eq!(counts.live.get(), 0);
+  }
+
+  #[test]
+  fn alloc_in_routes_through_alloc() {
+    let counts = CountingAlloc::default();
+    let mems: Vec<_> = (1 .. 4).map(|n| unsafe { HeapMem::<u64, _>::alloc_in(n, counts.clone()) }).collect();
+    assert_eq!(counts.allocs.get(), 3);
+    assert!(counts.live.get() >= 6 * size_of::<u64>());
+    drop(mems);
+    assert_eq!(counts.frees.get(), 3);
+    assert_eq!(counts.live.get(), 0);
   }
 }
@@ -23,107 +23,51 @@ fn sz2int(sz: usize) -> i32 {
   sz as _
 }
 
-pub trait VectorOps<T> where T: Copy {
-  fn matrix_vector_mult(&mut self,
-      w: MemArrayView2d<T>,
-      x: MemArrayView1d<T>);
-  fn transpose_matrix_vector_mult(&mut self,
-      w: MemArrayView2d<T>,
-      x: MemArrayView1d<T>);
+// How a matrix view is laid out for BLAS, which takes column-major matrices
+// with a leading dimension: returns whether the view is the transpose of such
+// a matrix, as `transpose()` views are, and the leading dimension. Reversed
+// (negative stride) and broadcast (zero stride) axes are not supported.
+#[cfg(feature = "mkl")]
+fn blas_layout(size: [usize; 2], stride: [usize; 2]) -> (bool, usize) {
+  let (trans, ld) = if stride[0] == 1 || size[0] <= 1 {
+    (false, if size[1] > 1 { stride[1] } else { size[0].max(1) })
+  } else if stride[1] == 1 || size[1] <= 1 {
+    (true, if size[0] > 1 { stride[0] } else { size[1].max(1) })
+  } else {
+    panic!("linalg: matrix has no unit stride: size: {:?} stride: {:?}",
+        size, [stride[0] as isize, stride[1] as isize]);
+  };
+  if ld as isize <= 0 {
+    panic!("linalg: matrix has a reversed or broadcast axis: size: {:?} stride: {:?}",
+        size, [stride[0] as isize, stride[1] as isize]);
+  }
+  (trans, ld)
 }
 
+// The increment of a vector view for BLAS.
 #[cfg(feature = "mkl")]
-impl<'a> VectorOps<f32> for MemArrayViewMut1d<'a, f32> {
-  fn matrix_vector_mult(&mut self,
-      w: MemArrayView2d<f32>,
-      x: MemArrayView1d<f32>)
-  {
-    assert_eq!(w.size()[0], self.size());
-    assert_eq!(w.size()[1], x.size());
-    assert_eq!(w.stride()[0], 1);
-    let alpha: f32 = 1.0;
-    let beta: f32 = 0.0;
-    unsafe { cblas_sgemv(
-        CBLAS_LAYOUT_CblasColMajor,
-        CBLAS_TRANSPOSE_CblasNoTrans,
-        sz2int(w.size()[0]),
-        sz2int(w.size()[1]),
-        alpha,
-        w.as_ptr(), sz2int(w.stride()[1]),
-        x.as_ptr(), sz2int(x.stride()),
-        beta,
-        self.as_mut_ptr(), sz2int(self.stride()),
-    ) };
+fn blas_inc(size: usize, stride: usize) -> usize {
+  if size <= 1 {
+    return 1;
   }
-
-  fn transpose_matrix_vector_mult(&mut self,
-      w: MemArrayView2d<f32>,
-      x: MemArrayView1d<f32>)
-  {
-    assert_eq!(w.size()[0], x.size());
-    assert_eq!(w.size()[1], self.size());
-    assert_eq!(w.stride()[0], 1);
-    let alpha: f32 = 1.0;
-    let beta: f32 = 0.0;
-    unsafe { cblas_sgemv(
-        CBLAS_LAYOUT_CblasColMajor,
-        CBLAS_TRANSPOSE_CblasTrans,
-        sz2int(w.size()[0]),
-        sz2int(w.size()[1]),
-        alpha,
-        w.as_ptr(), sz2int(w.stride()[1]),
-        x.as_ptr(), sz2int(x.stride()),
-        beta,
-        self.as_mut_ptr(), sz2int(self.stride()),
-    ) };
+  if stride as isize <= 0 {
+    panic!("linalg: vector is reversed or broadcast: size: {} stride: {}", size, stride as isize);
   }
+  stride
 }
 
 #[cfg(feature = "mkl")]
-impl<'a> VectorOps<f64> for MemArrayViewMut1d<'a, f64> {
-  fn matrix_vector_mult(&mut self,
-      w: MemArrayView2d<f64>,
-      x: MemArrayView1d<f64>)
-  {
-    assert_eq!(w.size()[0], self.size());
-    assert_eq!(w.size()[1], x.size());
-    assert_eq!(w.stride()[0], 1);
-    let alpha: f64 = 1.0;
-    let beta: f64 = 0.0;
-    unsafe { cblas_dgemv(
-        CBLAS_LAYOUT_CblasColMajor,
-        CBLAS_TRANSPOSE_CblasNoTrans,
-        sz2int(w.size()[0]),
-        sz2int(w.size()[1]),
-        alpha,
-        w.as_ptr(), sz2int(w.stride()[1]),
-        x.as_ptr(), sz2int(x.stride()),
-        beta,
-        self.as_mut_ptr(), sz2int(self.stride()),
-    ) };
-  }
+fn blas_trans(trans: bool) -> CBLAS_TRANSPOSE {
+  if trans { CBLAS_TRANSPOSE_CblasTrans } else { CBLAS_TRANSPOSE_CblasNoTrans }
+}
 
+pub trait VectorOps<T> where T: Copy {
+  fn matrix_vector_mult(&mut self,
+      w: MemArrayView2d<T>,
+      x: MemArrayView1d<T>);
   fn transpose_matrix_vector_mult(&mut self,
-      w: MemArrayView2d<f64>,
-      x: MemArrayView1d<f64>)
-  {
-    assert_eq!(w.size()[0], x.size());
-    assert_eq!(w.size()[1], self.size());
-    assert_eq!(w.stride()[0], 1);
-    let alpha: f64 = 1.0;
-    let beta: f64 = 0.0;
-    unsafe { cblas_dgemv(
-        CBLAS_LAYOUT_CblasColMajor,
-        CBLAS_TRANSPOSE_CblasTrans,
-        sz2int(w.size()[0]),
-        sz2int(w.size()[1]),
-        alpha,
-        w.as_ptr(), sz2int(w.stride()[1]),
-        x.as_ptr(), sz2int(x.stride()),
-        beta,
-        self.as_mut_ptr(), sz2int(self.stride()),
-    ) };
-  }
+      w: MemArrayView2d<T>,
+      x: MemArrayView1d<T>);
 }
 
 pub trait MatrixOps<T> where T: Copy {
@@ -138,94 +82,235 @@ pub trait MatrixOps<T> where T: Copy {
       x: MemArrayView2d<T>);
 }
 
-#[cfg(feature = "mkl")]
-impl<'a> MatrixOps<f32> for MemArrayViewMut2d<'a, f32> {
-  fn matrix_mult(&mut self,
-      w: MemArrayView2d<f32>,
-      x: MemArrayView2d<f32>)
-  {
-    assert_eq!(w.size()[0], self.size()[0]);
-    assert_eq!(w.size()[1], x.size()[0]);
-    assert_eq!(x.size()[1], self.size()[1]);
-    assert_eq!(w.stride()[0], 1);
-    assert_eq!(x.stride()[0], 1);
-    assert_eq!(self.stride()[0], 1);
-    let alpha: f32 = 1.0;
-    let beta: f32 = 0.0;
-    unsafe { cblas_sgemm(
-        CBLAS_LAYOUT_CblasColMajor,
-        CBLAS_TRANSPOSE_CblasNoTrans,
-        CBLAS_TRANSPOSE_CblasNoTrans,
-        sz2int(self.size()[0]),
-        sz2int(self.size()[1]),
-        sz2int(w.size()[1]),
-        alpha,
-        w.as_ptr(), sz2int(w.stride()[1]),
-        x.as_ptr(), sz2int(x.stride()[1]),
-        beta,
-        self.as_mut_ptr(), sz2int(self.stride()[1]),
-    ) };
+macro_rules! impl_blas_ops {
+  ($t:ty, $gemv:ident, $gemm:ident) => {
+    #[cfg(feature = "mkl")]
+    impl<'a> MemArrayViewMut1d<'a, $t> {
+      // Computes `op(w) x`, where `op(w)` is `w` or its transpose.
+      fn gemv(&mut self, w: MemArrayView2d<$t>, trans: bool, x: MemArrayView1d<$t>) {
+        let (m, n) = if trans { (w.size()[1], w.size()[0]) } else { (w.size()[0], w.size()[1]) };
+        assert_eq!(m, self.size());
+        assert_eq!(n, x.size());
+        let (w_trans, ldw) = blas_layout(w.size(), w.stride());
+        // BLAS wants the size of the matrix as stored.
+        let (rows, cols) = if w_trans { (w.size()[1], w.size()[0]) } else { (w.size()[0], w.size()[1]) };
+        let alpha: $t = 1.0;
+        let beta: $t = 0.0;
+        unsafe { $gemv(
+            CBLAS_LAYOUT_CblasColMajor,
+            blas_trans(trans != w_trans),
+            sz2int(rows),
+            sz2int(cols),
+            alpha,
+            w.as_ptr(), sz2int(ldw),
+            x.as_ptr(), sz2int(blas_inc(x.size(), x.stride())),
+            beta,
+            self.as_mut_ptr(), sz2int(blas_inc(self.size(), self.stride())),
+        ) };
+      }
+    }
+
+    #[cfg(feature = "mkl")]
+    impl<'a> VectorOps<$t> for MemArrayViewMut1d<'a, $t> {
+      fn matrix_vector_mult(&mut self,
+          w: MemArrayView2d<$t>,
+          x: MemArrayView1d<$t>)
+      {
+        self.gemv(w, false, x);
+      }
+
+      fn transpose_matrix_vector_mult(&mut self,
+          w: MemArrayView2d<$t>,
+          x: MemArrayView1d<$t>)
+      {
+        self.gemv(w, true, x);
+      }
+    }
+
+    #[cfg(feature = "mkl")]
+    impl<'a> MemArrayViewMut2d<'a, $t> {
+      // Computes `op(w) op(x)`, where `op` optionally transposes. A
+      // transposed output is computed as `op(x)^T op(w)^T`.
+      fn gemm(&mut self, w: MemArrayView2d<$t>, w_op: bool, x: MemArrayView2d<$t>, x_op: bool) {
+        let (m, n) = (self.size()[0], self.size()[1]);
+        let k = if w_op { w.size()[0] } else { w.size()[1] };
+        assert_eq!(if w_op { w.size()[1] } else { w.size()[0] }, m);
+        assert_eq!(if x_op { x.size()[1] } else { x.size()[0] }, k);
+        assert_eq!(if x_op { x.size()[0] } else { x.size()[1] }, n);
+        let (w_trans, ldw) = blas_layout(w.size(), w.stride());
+        let (x_trans, ldx) = blas_layout(x.size(), x.stride());
+        let (y_trans, ldy) = blas_layout(self.size(), self.stride());
+        let alpha: $t = 1.0;
+        let beta: $t = 0.0;
+        if !y_trans {
+          unsafe { $gemm(
+              CBLAS_LAYOUT_CblasColMajor,
+              blas_trans(w_op != w_trans),
+              blas_trans(x_op != x_trans),
+              sz2int(m),
+              sz2int(n),
+              sz2int(k),
+              alpha,
+              w.as_ptr(), sz2int(ldw),
+              x.as_ptr(), sz2int(ldx),
+              beta,
+              self.as_mut_ptr(), sz2int(ldy),
+          ) };
+        } else {
+          unsafe { $gemm(
+              CBLAS_LAYOUT_CblasColMajor,
+              blas_trans(x_op == x_trans),
+              blas_trans(w_op == w_trans),
+              sz2int(n),
+              sz2int(m),
+              sz2int(k),
+              alpha,
+              x.as_ptr(), sz2int(ldx),
+              w.as_ptr(), sz2int(ldw),
+              beta,
+              self.as_mut_ptr(), sz2int(ldy),
+          ) };
+        }
+      }
+    }
+
+    #[cfg(feature = "mkl")]
+    impl<'a> MatrixOps<$t> for MemArrayViewMut2d<'a, $t> {
+      fn matrix_mult(&mut self,
+          w: MemArrayView2d<$t>,
+          x: MemArrayView2d<$t>)
+      {
+        self.gemm(w, false, x, false);
+      }
+
+      fn left_transpose_matrix_mult(&mut self,
+          w: MemArrayView2d<$t>,
+          x: MemArrayView2d<$t>)
+      {
+        self.gemm(w, true, x, false);
+      }
+
+      fn right_transpose_matrix_mult(&mut self,
+          w: MemArrayView2d<$t>,
+          x: MemArrayView2d<$t>)
+      {
+        self.gemm(w, false, x, true);
+      }
+    }
+  };
+}
+
+impl_blas_ops!(f32, cblas_sgemv, cblas_sgemm);
+impl_blas_ops!(f64, cblas_dgemv, cblas_dgemm);
+
+#[cfg(all(test, feature = "mkl"))]
+mod tests {
+  use super::*;
+
+  fn iota(size: [usize; 2], scale: f32) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], scale * (i + 10 * j) as f32);
+      }
+    }
+    a
   }
 
-  fn left_transpose_matrix_mult(&mut self,
-      w: MemArrayView2d<f32>,
-      x: MemArrayView2d<f32>)
-  {
-    // TODO
-    unimplemented!();
+  fn naive_mult(w: &MemArrayView2d<f32>, x: &MemArrayView2d<f32>) -> MemArray2d<f32> {
+    let (m, k, n) = (w.size()[0], w.size()[1], x.size()[1]);
+    let mut y = MemArray2d::zeros([m, n]);
+    for j in 0 .. n {
+      for i in 0 .. m {
+        let mut s = 0.0;
+        for p in 0 .. k {
+          s += w[[i, p]] * x[[p, j]];
+        }
+        y.set([i, j], s);
+      }
+    }
+    y
   }
 
-  fn right_transpose_matrix_mult(&mut self,
-      w: MemArrayView2d<f32>,
-      x: MemArrayView2d<f32>)
-  {
-    // TODO
-    unimplemented!();
+  fn assert_same(y: &MemArray2d<f32>, z: &MemArray2d<f32>) {
+    assert_eq!(y.size, z.size);
+    for j in 0 .. y.size[1] {
+      for i in 0 .. y.size[0] {
+        assert_eq!(y[[i, j]], z[[i, j]]);
+      }
+    }
   }
-}
 
-#[cfg(feature = "mkl")]
-impl<'a> MatrixOps<f64> for MemArrayViewMut2d<'a, f64> {
-  fn matrix_mult(&mut self,
-      w: MemArrayView2d<f64>,
-      x: MemArrayView2d<f64>)
-  {
-    assert_eq!(w.size()[0], self.size()[0]);
-    assert_eq!(w.size()[1], x.size()[0]);
-    assert_eq!(x.size()[1], self.size()[1]);
-    assert_eq!(w.stride()[0], 1);
-    assert_eq!(x.stride()[0], 1);
-    assert_eq!(self.stride()[0], 1);
-    let alpha: f64 = 1.0;
-    let beta: f64 = 0.0;
-    unsafe { cblas_dgemm(
-        CBLAS_LAYOUT_CblasColMajor,
-        CBLAS_TRANSPOSE_CblasNoTrans,
-        CBLAS_TRANSPOSE_CblasNoTrans,
-        sz2int(self.size()[0]),
-        sz2int(self.size()[1]),
-        sz2int(w.size()[1]),
-        alpha,
-        w.as_ptr(), sz2int(w.stride()[1]),
-        x.as_ptr(), sz2int(x.stride()[1]),
-        beta,
-        self.as_mut_ptr(), sz2int(self.stride()[1]),
-    ) };
+  #[test]
+  fn matrix_mult_transposed_views() {
+    let wt = iota([3, 2], 1.0);
+    let x = iota([3, 4], 0.5);
+    let expected = naive_mult(&wt.as_view().transpose(), &x.as_view());
+    let mut y = MemArray2d::<f32>::zeros([2, 4]);
+    y.as_view_mut().matrix_mult(wt.as_view().transpose(), x.as_view());
+    assert_same(&y, &expected);
+    let mut y2 = MemArray2d::<f32>::zeros([2, 4]);
+    y2.as_view_mut().left_transpose_matrix_mult(wt.as_view(), x.as_view());
+    assert_same(&y2, &expected);
+    let xt = x.as_view().transpose().to_packed();
+    let mut y3 = MemArray2d::<f32>::zeros([2, 4]);
+    y3.as_view_mut().matrix_mult(wt.as_view().transpose(), xt.as_view().transpose());
+    assert_same(&y3, &expected);
+    let mut y4 = MemArray2d::<f32>::zeros([2, 4]);
+    y4.as_view_mut().right_transpose_matrix_mult(wt.as_view().transpose(), xt.as_view());
+    assert_same(&y4, &expected);
   }
 
-  fn left_transpose_matrix_mult(&mut self,
-      w: MemArrayView2d<f64>,
-      x: MemArrayView2d<f64>)
-  {
-    // TODO
-    unimplemented!();
+  #[test]
+  fn matrix_mult_transposed_output() {
+    let w = iota([2, 3], 1.0);
+    let x = iota([3, 4], 0.5);
+    let expected = naive_mult(&w.as_view(), &x.as_view());
+    let mut yt = MemArray2d::<f32>::zeros([4, 2]);
+    yt.as_view_mut().transpose().matrix_mult(w.as_view(), x.as_view());
+    assert_same(&yt.as_view().transpose().to_packed(), &expected);
   }
 
-  fn right_transpose_matrix_mult(&mut self,
-      w: MemArrayView2d<f64>,
-      x: MemArrayView2d<f64>)
-  {
-    // TODO
-    unimplemented!();
+  #[test]
+  fn matrix_vector_mult_transposed_view() {
+    let wt = iota([3, 2], 1.0);
+    let x = iota([3, 1], 1.0);
+    let expected = naive_mult(&wt.as_view().transpose(), &x.as_view());
+    let mut y = MemArray1d::<f32>::zeros(2);
+    y.as_view_mut().matrix_vector_mult(wt.as_view().transpose(), x.as_view().index_axis(1, 0));
+    assert_eq!(y[0], expected[[0, 0]]);
+    assert_eq!(y[1], expected[[1, 0]]);
+    let mut y2 = MemArray1d::<f32>::zeros(2);
+    y2.as_view_mut().transpose_matrix_vector_mult(wt.as_view(), x.as_view().index_axis(1, 0));
+    assert_eq!(y2[0], expected[[0, 0]]);
+    assert_eq!(y2[1], expected[[1, 0]]);
+  }
+
+  #[test]
+  #[should_panic(expected = "linalg: matrix has a reversed or broadcast axis")]
+  fn matrix_mult_reversed_view() {
+    let w = iota([2, 3], 1.0);
+    let x = iota([3, 4], 0.5);
+    let wr = w.as_view().slice::<Index2d>(&[SliceSpec::all(), SliceSpec::all().rev()]);
+    let mut y = MemArray2d::<f32>::zeros([2, 4]);
+    y.as_view_mut().matrix_mult(wr, x.as_view());
+  }
+
+  #[test]
+  #[should_panic(expected = "linalg: matrix has a reversed or broadcast axis")]
+  fn matrix_mult_broadcast_view() {
+    let w = MemArray1d::<f32>::zeros(2);
+    let x = iota([3, 4], 0.5);
+    let mut y = MemArray2d::<f32>::zeros([2, 4]);
+    y.as_view_mut().matrix_mult(w.broadcast_to([2, 3]).unwrap(), x.as_view());
+  }
+
+  #[test]
+  #[should_panic(expected = "linalg: vector is reversed or broadcast: size: 3 stride: -1")]
+  fn matrix_vector_mult_reversed_vector() {
+    let w = iota([2, 3], 1.0);
+    let x = MemArray1d::<f32>::zeros(3);
+    let mut y = MemArray1d::<f32>::zeros(2);
+    y.as_view_mut().matrix_vector_mult(w.as_view(), x.as_view().slice(&[SliceSpec::all().rev()]));
   }
 }
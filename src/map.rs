@@ -0,0 +1,198 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+// Elements are visited in the same (column-major) order in every operand,
+// whatever their strides, so zipped operands are matched up by index.
+
+use ::{Mem, MemArray, MemArrayView, MemArrayViewMut, ReadOnlyMem, ZeroBits};
+use iter::{Iter};
+
+use arrayidx::*;
+
+fn map_into<Idx, T, U, F>(dst: &mut MemArray<Idx, U>, src: Iter<T>, mut f: F) where Idx: ArrayIndex, T: Copy, U: ZeroBits, F: FnMut(T) -> U {
+  let dst = dst.memory_mut().as_mut_slice();
+  for (y, &x) in dst.iter_mut().zip(src) {
+    *y = f(x);
+  }
+}
+
+impl<'a, Idx, T> MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  /// Returns a new packed array with `f` applied to each element.
+  pub fn map<U, F>(&self, f: F) -> MemArray<Idx, U> where U: ZeroBits, F: FnMut(T) -> U {
+    let mut dst = MemArray::zeros(self.size.clone());
+    map_into(&mut dst, self.iter(), f);
+    dst
+  }
+}
+
+impl<'a, Idx, T> MemArrayViewMut<'a, Idx, T> where Idx: ArrayIndex, T: Copy + 'static {
+  pub fn map<U, F>(&self, f: F) -> MemArray<Idx, U> where U: ZeroBits, F: FnMut(T) -> U {
+    let mut dst = MemArray::zeros(self.size.clone());
+    map_into(&mut dst, self.iter(), f);
+    dst
+  }
+
+  pub fn map_inplace<F>(&mut self, mut f: F) where F: FnMut(&mut T) {
+    if let Some(dst) = self.flat_slice_mut() {
+      for y in dst.iter_mut() {
+        f(y);
+      }
+      return;
+    }
+    for y in self.iter_mut() {
+      f(y);
+    }
+  }
+
+  /// Calls `f` with each element of this view and the element at the same
+  /// index of `other`, which must have the same size.
+  pub fn zip_apply<U, F>(&mut self, other: &MemArrayView<Idx, U>, mut f: F) where U: Copy + 'static, F: FnMut(&mut T, U) {
+    assert_eq!(self.size, other.size,
+        "MemArrayViewMut::zip_apply: size mismatch: size: {:?} other size: {:?}", self.size, other.size);
+    if let Some(src) = other.flat_slice() {
+      if let Some(dst) = self.flat_slice_mut() {
+        for (y, &x) in dst.iter_mut().zip(src.iter()) {
+          f(y, x);
+        }
+        return;
+      }
+    }
+    for (y, &x) in self.iter_mut().zip(other.iter()) {
+      f(y, x);
+    }
+  }
+
+  /// Like `zip_apply`, with two other views.
+  pub fn zip3<U, V, F>(&mut self, a: &MemArrayView<Idx, U>, b: &MemArrayView<Idx, V>, mut f: F)
+  where U: Copy + 'static, V: Copy + 'static, F: FnMut(&mut T, U, V),
+  {
+    assert_eq!(self.size, a.size,
+        "MemArrayViewMut::zip3: size mismatch: size: {:?} a size: {:?}", self.size, a.size);
+    assert_eq!(self.size, b.size,
+        "MemArrayViewMut::zip3: size mismatch: size: {:?} b size: {:?}", self.size, b.size);
+    if let (Some(src_a), Some(src_b)) = (a.flat_slice(), b.flat_slice()) {
+      if let Some(dst) = self.flat_slice_mut() {
+        for ((y, &x), &z) in dst.iter_mut().zip(src_a.iter()).zip(src_b.iter()) {
+          f(y, x, z);
+        }
+        return;
+      }
+    }
+    for ((y, &x), &z) in self.iter_mut().zip(a.iter()).zip(b.iter()) {
+      f(y, x, z);
+    }
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy + 'static, M: ReadOnlyMem<T> {
+  pub fn map<U, F>(&self, f: F) -> MemArray<Idx, U> where U: ZeroBits, F: FnMut(T) -> U {
+    self.as_view().map(f)
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: Copy + 'static, M: Mem<T> {
+  pub fn map_inplace<F>(&mut self, f: F) where F: FnMut(&mut T) {
+    self.as_view_mut().map_inplace(f);
+  }
+
+  pub fn zip_apply<U, F>(&mut self, other: &MemArrayView<Idx, U>, f: F) where U: Copy + 'static, F: FnMut(&mut T, U) {
+    self.as_view_mut().zip_apply(other, f);
+  }
+
+  pub fn zip3<U, V, F>(&mut self, a: &MemArrayView<Idx, U>, b: &MemArrayView<Idx, V>, f: F)
+  where U: Copy + 'static, V: Copy + 'static, F: FnMut(&mut T, U, V),
+  {
+    self.as_view_mut().zip3(a, b, f);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ::{DenseArray, MemArray2d};
+
+  fn iota(size: [usize; 2]) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
+    }
+    a
+  }
+
+  #[test]
+  fn map_packed_and_strided() {
+    let a = iota([3, 2]);
+    let m = a.map(|x| 2.0 * x);
+    assert_eq!(m.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 2.0, 4.0, 20.0, 22.0, 24.0]);
+    let s = a.as_view().view((1 .., ..));
+    assert!(!s.is_packed());
+    let m = s.map(|x| x as i32 + 1);
+    assert_eq!(m.size, [2, 2]);
+    assert_eq!(m.as_view().iter().cloned().collect::<Vec<_>>(), vec![2, 3, 12, 13]);
+  }
+
+  #[test]
+  fn map_inplace_packed_and_strided() {
+    let mut a = iota([3, 2]);
+    a.map_inplace(|x| *x += 1.0);
+    assert_eq!(a[[2, 1]], 13.0);
+    {
+      let mut s = a.as_view_mut().view_mut((1 .., ..));
+      assert!(!s.is_packed());
+      s.map_inplace(|x| *x = -*x);
+    }
+    assert_eq!(a.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0, -2.0, -3.0, 11.0, -12.0, -13.0]);
+  }
+
+  #[test]
+  fn zip_apply_packed_and_strided() {
+    let mut a = iota([2, 2]);
+    let b = iota([2, 2]);
+    a.zip_apply(&b.as_view(), |y, x| *y += x);
+    assert_eq!(a.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 2.0, 20.0, 22.0]);
+    // Strided on both sides, matched up by index.
+    let c = iota([3, 2]);
+    let mut d = MemArray2d::<f32>::zeros([3, 2]);
+    d.as_view_mut().view_mut((.. 2, ..)).zip_apply(&c.as_view().view((1 .., ..)), |y, x| *y = x);
+    assert_eq!(d.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0, 2.0, 0.0, 11.0, 12.0, 0.0]);
+  }
+
+  #[test]
+  fn zip3_packed_and_strided() {
+    let a = iota([3, 2]);
+    let b = iota([2, 2]);
+    let mut d = MemArray2d::<f32>::zeros([2, 2]);
+    d.zip3(&a.as_view().view((1 .., ..)), &b.as_view(), |z, x, y| *z = x - y);
+    assert_eq!(d.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0; 4]);
+    d.zip3(&b.as_view(), &b.as_view(), |z, x, y| *z = x * y);
+    assert_eq!(d.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 1.0, 100.0, 121.0]);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayViewMut::zip_apply: size mismatch")]
+  fn zip_apply_mismatch() {
+    let mut a = iota([3, 2]);
+    a.zip_apply(&iota([2, 3]).as_view(), |y, x| *y = x);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayViewMut::zip3: size mismatch: size: [2, 2] b size")]
+  fn zip3_mismatch() {
+    let mut d = MemArray2d::<f32>::zeros([2, 2]);
+    d.zip3(&iota([2, 2]).as_view(), &iota([2, 3]).as_view(), |z, x, y| *z = x + y);
+  }
+}
@@ -0,0 +1,410 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{Mem, MemArray, MemArrayView, MemArrayViewMut, ReadOnlyMem, ZeroBits};
+
+use arrayidx::*;
+
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// The index type of the result of broadcasting arrays with index types
+/// `Self` and `Rhs` together, i.e. the one of higher rank.
+pub trait BroadcastIndex<Rhs>: ArrayIndex {
+  type Output: ArrayIndex;
+}
+
+macro_rules! impl_broadcast_index {
+  ($($a:ty, $b:ty => $o:ty;)*) => {
+    $( impl BroadcastIndex<$b> for $a { type Output = $o; } )*
+  };
+}
+
+impl_broadcast_index! {
+  Index0d, Index0d => Index0d;
+  Index0d, Index1d => Index1d;
+  Index0d, Index2d => Index2d;
+  Index0d, Index3d => Index3d;
+  Index0d, Index4d => Index4d;
+  Index0d, Index5d => Index5d;
+  Index1d, Index0d => Index1d;
+  Index1d, Index1d => Index1d;
+  Index1d, Index2d => Index2d;
+  Index1d, Index3d => Index3d;
+  Index1d, Index4d => Index4d;
+  Index1d, Index5d => Index5d;
+  Index2d, Index0d => Index2d;
+  Index2d, Index1d => Index2d;
+  Index2d, Index2d => Index2d;
+  Index2d, Index3d => Index3d;
+  Index2d, Index4d => Index4d;
+  Index2d, Index5d => Index5d;
+  Index3d, Index0d => Index3d;
+  Index3d, Index1d => Index3d;
+  Index3d, Index2d => Index3d;
+  Index3d, Index3d => Index3d;
+  Index3d, Index4d => Index4d;
+  Index3d, Index5d => Index5d;
+  Index4d, Index0d => Index4d;
+  Index4d, Index1d => Index4d;
+  Index4d, Index2d => Index4d;
+  Index4d, Index3d => Index4d;
+  Index4d, Index4d => Index4d;
+  Index4d, Index5d => Index5d;
+  Index5d, Index0d => Index5d;
+  Index5d, Index1d => Index5d;
+  Index5d, Index2d => Index5d;
+  Index5d, Index3d => Index5d;
+  Index5d, Index4d => Index5d;
+  Index5d, Index5d => Index5d;
+}
+
+// Operands of different sizes are broadcast to a common size, as in
+// `MemArrayView::broadcast_to`, and the result has the higher rank of the
+// two. Assignment operators broadcast the right operand to the size of the
+// left one, so it may not have a higher rank.
+//
+// A `MemArrayViewMut` may only appear on the left of an assignment operator
+// and under `Neg`; it has no `ReadOnlyMem` to lend a `MemArrayView`, so it is
+// excluded from the binary operators. Copy it with `to_packed` first.
+
+// Computes the size that arrays of sizes `a` and `b` broadcast to, or returns
+// `None` if they are incompatible.
+fn broadcast_size(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+  let nd = if a.len() > b.len() { a.len() } else { b.len() };
+  let mut size = Vec::with_capacity(nd);
+  for d in 0 .. nd {
+    let sa = if d < a.len() { a[d] } else { 1 };
+    let sb = if d < b.len() { b[d] } else { 1 };
+    if sa == sb || sb == 1 {
+      size.push(sa);
+    } else if sa == 1 {
+      size.push(sb);
+    } else {
+      return None;
+    }
+  }
+  Some(size)
+}
+
+fn binary_op<Idx, Idx2, T, F>(lhs: &MemArrayView<Idx, T>, rhs: &MemArrayView<Idx2, T>, name: &str, mut f: F) -> MemArray<Idx::Output, T>
+where Idx: BroadcastIndex<Idx2>, Idx2: ArrayIndex, T: ZeroBits + 'static, F: FnMut(T, T) -> T,
+{
+  let size = match broadcast_size(&lhs.size.to_nd(), &rhs.size.to_nd()) {
+    None => panic!("MemArray: {}: cannot broadcast: lhs size: {:?} rhs size: {:?}", name, lhs.size, rhs.size),
+    Some(size) => Idx::Output::from_nd(size),
+  };
+  let lhs = lhs.clone().broadcast_to(size.clone()).unwrap();
+  let rhs = rhs.clone().broadcast_to(size.clone()).unwrap();
+  let mut dst = MemArray::zeros(size);
+  dst.zip3(&lhs, &rhs, |z, x, y| *z = f(x, y));
+  dst
+}
+
+fn assign_op<Idx, Idx2, T, F>(lhs: &mut MemArrayViewMut<Idx, T>, rhs: &MemArrayView<Idx2, T>, name: &str, f: F)
+where Idx: ArrayIndex, Idx2: ArrayIndex, T: Copy + 'static, F: FnMut(&mut T, T),
+{
+  let rhs = match rhs.clone().broadcast_to(lhs.size.clone()) {
+    Err(_) => panic!("MemArrayViewMut: {}: cannot broadcast: lhs size: {:?} rhs size: {:?}", name, lhs.size, rhs.size),
+    Ok(rhs) => rhs,
+  };
+  lhs.zip_apply(&rhs, f);
+}
+
+macro_rules! impl_binary_op {
+  ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident) => {
+    impl<'a, 'b, 'c, 'd, Idx, Idx2, T> $Op<&'c MemArrayView<'d, Idx2, T>> for &'a MemArrayView<'b, Idx, T>
+    where Idx: BroadcastIndex<Idx2>, Idx2: ArrayIndex, T: ZeroBits + $Op<Output=T> + 'static,
+    {
+      type Output = MemArray<Idx::Output, T>;
+
+      fn $op(self, rhs: &'c MemArrayView<'d, Idx2, T>) -> MemArray<Idx::Output, T> {
+        binary_op(self, rhs, stringify!($op), $Op::$op)
+      }
+    }
+
+    impl<'a, 'c, 'd, Idx, Idx2, T, M> $Op<&'c MemArrayView<'d, Idx2, T>> for &'a MemArray<Idx, T, M>
+    where Idx: BroadcastIndex<Idx2>, Idx2: ArrayIndex, T: ZeroBits + $Op<Output=T> + 'static, M: ReadOnlyMem<T>,
+    {
+      type Output = MemArray<Idx::Output, T>;
+
+      fn $op(self, rhs: &'c MemArrayView<'d, Idx2, T>) -> MemArray<Idx::Output, T> {
+        binary_op(&self.as_view(), rhs, stringify!($op), $Op::$op)
+      }
+    }
+
+    impl<'a, 'b, 'c, Idx, Idx2, T, M> $Op<&'c MemArray<Idx2, T, M>> for &'a MemArrayView<'b, Idx, T>
+    where Idx: BroadcastIndex<Idx2>, Idx2: ArrayIndex, T: ZeroBits + $Op<Output=T> + 'static, M: ReadOnlyMem<T>,
+    {
+      type Output = MemArray<Idx::Output, T>;
+
+      fn $op(self, rhs: &'c MemArray<Idx2, T, M>) -> MemArray<Idx::Output, T> {
+        binary_op(self, &rhs.as_view(), stringify!($op), $Op::$op)
+      }
+    }
+
+    impl<'a, 'c, Idx, Idx2, T, M, M2> $Op<&'c MemArray<Idx2, T, M2>> for &'a MemArray<Idx, T, M>
+    where Idx: BroadcastIndex<Idx2>, Idx2: ArrayIndex, T: ZeroBits + $Op<Output=T> + 'static, M: ReadOnlyMem<T>, M2: ReadOnlyMem<T>,
+    {
+      type Output = MemArray<Idx::Output, T>;
+
+      fn $op(self, rhs: &'c MemArray<Idx2, T, M2>) -> MemArray<Idx::Output, T> {
+        binary_op(&self.as_view(), &rhs.as_view(), stringify!($op), $Op::$op)
+      }
+    }
+
+    impl<'a, 'c, 'd, Idx, Idx2, T> $OpAssign<&'c MemArrayView<'d, Idx2, T>> for MemArrayViewMut<'a, Idx, T>
+    where Idx: ArrayIndex, Idx2: ArrayIndex, T: Copy + $OpAssign + 'static,
+    {
+      fn $op_assign(&mut self, rhs: &'c MemArrayView<'d, Idx2, T>) {
+        assign_op(self, rhs, stringify!($op_assign), $OpAssign::$op_assign);
+      }
+    }
+
+    impl<'a, 'c, Idx, Idx2, T, M> $OpAssign<&'c MemArray<Idx2, T, M>> for MemArrayViewMut<'a, Idx, T>
+    where Idx: ArrayIndex, Idx2: ArrayIndex, T: Copy + $OpAssign + 'static, M: ReadOnlyMem<T>,
+    {
+      fn $op_assign(&mut self, rhs: &'c MemArray<Idx2, T, M>) {
+        assign_op(self, &rhs.as_view(), stringify!($op_assign), $OpAssign::$op_assign);
+      }
+    }
+
+    impl<'c, 'd, Idx, Idx2, T, M> $OpAssign<&'c MemArrayView<'d, Idx2, T>> for MemArray<Idx, T, M>
+    where Idx: ArrayIndex, Idx2: ArrayIndex, T: Copy + $OpAssign + 'static, M: Mem<T>,
+    {
+      fn $op_assign(&mut self, rhs: &'c MemArrayView<'d, Idx2, T>) {
+        assign_op(&mut self.as_view_mut(), rhs, stringify!($op_assign), $OpAssign::$op_assign);
+      }
+    }
+
+    impl<'c, Idx, Idx2, T, M, M2> $OpAssign<&'c MemArray<Idx2, T, M2>> for MemArray<Idx, T, M>
+    where Idx: ArrayIndex, Idx2: ArrayIndex, T: Copy + $OpAssign + 'static, M: Mem<T>, M2: ReadOnlyMem<T>,
+    {
+      fn $op_assign(&mut self, rhs: &'c MemArray<Idx2, T, M2>) {
+        assign_op(&mut self.as_view_mut(), &rhs.as_view(), stringify!($op_assign), $OpAssign::$op_assign);
+      }
+    }
+  };
+}
+
+impl_binary_op!(Add, add, AddAssign, add_assign);
+impl_binary_op!(Sub, sub, SubAssign, sub_assign);
+impl_binary_op!(Mul, mul, MulAssign, mul_assign);
+impl_binary_op!(Div, div, DivAssign, div_assign);
+
+impl<'a, 'b, Idx, T> Neg for &'a MemArrayView<'b, Idx, T> where Idx: ArrayIndex, T: ZeroBits + Neg<Output=T> + 'static {
+  type Output = MemArray<Idx, T>;
+
+  fn neg(self) -> MemArray<Idx, T> {
+    self.map(|x| -x)
+  }
+}
+
+impl<'a, Idx, T, M> Neg for &'a MemArray<Idx, T, M> where Idx: ArrayIndex, T: ZeroBits + Neg<Output=T> + 'static, M: ReadOnlyMem<T> {
+  type Output = MemArray<Idx, T>;
+
+  fn neg(self) -> MemArray<Idx, T> {
+    self.map(|x| -x)
+  }
+}
+
+impl<'a, 'b, Idx, T> Neg for &'a MemArrayViewMut<'b, Idx, T> where Idx: ArrayIndex, T: ZeroBits + Neg<Output=T> + 'static {
+  type Output = MemArray<Idx, T>;
+
+  fn neg(self) -> MemArray<Idx, T> {
+    self.map(|x| -x)
+  }
+}
+
+// Scalar operands are implemented per element type, since a generic scalar
+// type parameter would overlap with the array operands above.
+macro_rules! impl_scalar_op {
+  ($t:ty, $Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident) => {
+    impl<'a, 'b, Idx> $Op<$t> for &'a MemArrayView<'b, Idx, $t> where Idx: ArrayIndex {
+      type Output = MemArray<Idx, $t>;
+
+      fn $op(self, rhs: $t) -> MemArray<Idx, $t> {
+        self.map(|x| $Op::$op(x, rhs))
+      }
+    }
+
+    impl<'a, Idx, M> $Op<$t> for &'a MemArray<Idx, $t, M> where Idx: ArrayIndex, M: ReadOnlyMem<$t> {
+      type Output = MemArray<Idx, $t>;
+
+      fn $op(self, rhs: $t) -> MemArray<Idx, $t> {
+        self.map(|x| $Op::$op(x, rhs))
+      }
+    }
+
+    impl<'a, Idx> $OpAssign<$t> for MemArrayViewMut<'a, Idx, $t> where Idx: ArrayIndex {
+      fn $op_assign(&mut self, rhs: $t) {
+        self.map_inplace(|x| $OpAssign::$op_assign(x, rhs));
+      }
+    }
+
+    impl<Idx, M> $OpAssign<$t> for MemArray<Idx, $t, M> where Idx: ArrayIndex, M: Mem<$t> {
+      fn $op_assign(&mut self, rhs: $t) {
+        self.map_inplace(|x| $OpAssign::$op_assign(x, rhs));
+      }
+    }
+  };
+}
+
+macro_rules! impl_scalar_ops {
+  ($($t:ty),*) => {
+    $(
+      impl_scalar_op!($t, Add, add, AddAssign, add_assign);
+      impl_scalar_op!($t, Sub, sub, SubAssign, sub_assign);
+      impl_scalar_op!($t, Mul, mul, MulAssign, mul_assign);
+      impl_scalar_op!($t, Div, div, DivAssign, div_assign);
+    )*
+  };
+}
+
+impl_scalar_ops!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+#[cfg(test)]
+mod tests {
+  use ::{MemArray1d, MemArray2d, MemArray3d};
+
+  fn iota1(n: usize) -> MemArray1d<f32> {
+    let mut a = MemArray1d::zeros(n);
+    for i in 0 .. n {
+      a.set(i, i as f32);
+    }
+    a
+  }
+
+  #[test]
+  fn broadcast_lower_rank_either_side() {
+    let mut a = MemArray2d::<f32>::zeros([3, 2]);
+    a.map_inplace(|x| *x = 10.0);
+    let b = iota1(3);
+    let c = &a + &b;
+    assert_eq!(c.size, [3, 2]);
+    assert_eq!(c[[2, 1]], 12.0);
+    let d = &b - &a;
+    assert_eq!(d.size, [3, 2]);
+    assert_eq!(d[[1, 1]], -9.0);
+    let e = &b.as_view() * &a.as_view();
+    assert_eq!(e.size, [3, 2]);
+    assert_eq!(e[[2, 0]], 20.0);
+  }
+
+  #[test]
+  fn broadcast_size_one_axes() {
+    let col = iota1(3).as_view().insert_axis(1).to_packed();
+    let row = iota1(4).as_view().insert_axis(0).to_packed();
+    let s = &col + &row;
+    assert_eq!(s.size, [3, 4]);
+    assert_eq!(s[[2, 3]], 5.0);
+    let t = &row + &col;
+    assert_eq!(t.size, [3, 4]);
+    assert_eq!(t[[1, 2]], 3.0);
+    // Shapes are aligned at axis 0: [1, 4] and [3, 1, 2] give [3, 4, 2].
+    let a = MemArray3d::<f32>::zeros([3, 1, 2]).map(|_| 1.0f32);
+    let u = &row + &a;
+    assert_eq!(u.size, [3, 4, 2]);
+    assert_eq!(u[[2, 3, 1]], 4.0);
+    let v = &a - &row;
+    assert_eq!(v.size, [3, 4, 2]);
+    assert_eq!(v[[0, 3, 0]], -2.0);
+  }
+
+  #[test]
+  fn broadcast_assign() {
+    let mut a = MemArray2d::<f32>::zeros([3, 2]);
+    a += &iota1(3);
+    assert_eq!(a[[2, 1]], 2.0);
+    {
+      let mut v = a.as_view_mut();
+      v *= &iota1(3).as_view().insert_axis(1);
+    }
+    assert_eq!(a[[2, 1]], 4.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayViewMut: add_assign: cannot broadcast")]
+  fn broadcast_assign_higher_rank() {
+    let mut b = iota1(3);
+    b += &MemArray2d::<f32>::zeros([3, 2]);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArray: add: cannot broadcast")]
+  fn broadcast_incompatible() {
+    let _ = &iota1(3) + &MemArray2d::<f32>::zeros([4, 2]);
+  }
+
+  #[test]
+  fn elementwise_ops() {
+    let a = iota1(4).map(|x| x + 1.0);
+    let b = iota1(4).map(|x| 2.0 * x + 2.0);
+    let d = &b - &a;
+    let q = &b / &a;
+    let p = &a * &b;
+    for i in 0 .. 4 {
+      assert_eq!(d[i], (i + 1) as f32);
+      assert_eq!(q[i], 2.0);
+      assert_eq!(p[i], 2.0 * ((i + 1) * (i + 1)) as f32);
+    }
+    let mut c = b.as_view().to_packed();
+    c -= &a;
+    c /= &a.as_view();
+    assert_eq!(c.as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0; 4]);
+  }
+
+  #[test]
+  fn scalar_ops() {
+    let a = iota1(4);
+    assert_eq!((&a + 1.0).as_view().iter().cloned().collect::<Vec<_>>(), vec![1.0, 2.0, 3.0, 4.0]);
+    assert_eq!((&a - 1.0).as_view().iter().cloned().collect::<Vec<_>>(), vec![-1.0, 0.0, 1.0, 2.0]);
+    assert_eq!((&a.as_view() * 3.0).as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 3.0, 6.0, 9.0]);
+    assert_eq!((&a.as_view() / 2.0).as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, 0.5, 1.0, 1.5]);
+    let mut b = MemArray2d::<i32>::zeros([2, 3]);
+    b += 7;
+    b *= 2;
+    {
+      let mut v = b.as_view_mut();
+      v -= 4;
+      v /= 5;
+    }
+    assert_eq!(b.as_view().iter().cloned().collect::<Vec<_>>(), vec![2; 6]);
+  }
+
+  #[test]
+  fn neg_ops() {
+    let mut a = iota1(3);
+    let n = -&a;
+    assert_eq!(n.as_view().iter().cloned().collect::<Vec<_>>(), vec![0.0, -1.0, -2.0]);
+    let n = -&a.as_view();
+    assert_eq!(n[2], -2.0);
+    let n = -&a.as_view_mut();
+    assert_eq!(n[1], -1.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArray: sub: cannot broadcast")]
+  fn sub_mismatch() {
+    let _ = &iota1(3) - &iota1(4);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayViewMut: div_assign: cannot broadcast")]
+  fn div_assign_mismatch() {
+    let mut a = iota1(3);
+    a /= &iota1(2);
+  }
+}
@@ -0,0 +1,344 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{Mem, MemArray, ReadOnlyMem, ZeroBits};
+use accounting::{MemAccount, TagMem};
+
+use arrayidx::{ArrayIndex};
+
+use std::alloc::{Layout, alloc, dealloc, handle_alloc_error};
+use std::collections::{HashMap};
+use std::mem::{align_of, size_of};
+use std::ptr::{write_bytes};
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::sync::{Arc, Mutex};
+
+const POOL_ALIGN:     usize = 64;
+const POOL_MIN_CLASS: usize = 64;
+
+thread_local! {
+  static DEFAULT_POOL: MemPool = MemPool::new();
+}
+
+fn size_class(phsz: usize) -> Option<usize> {
+  if phsz <= POOL_MIN_CLASS {
+    Some(POOL_MIN_CLASS)
+  } else {
+    phsz.checked_next_power_of_two()
+  }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MemPoolStats {
+  /// Allocations served from a cached block.
+  pub hits:         usize,
+  /// Allocations that went to the global allocator.
+  pub misses:       usize,
+  /// Bytes in cached blocks that are not currently in use.
+  pub bytes_held:   usize,
+  /// Bytes in blocks currently handed out.
+  pub bytes_in_use: usize,
+}
+
+struct PoolBlock {
+  ptr:  *mut u8,
+}
+
+// Cached blocks are not aliased by anyone else.
+unsafe impl Send for PoolBlock {}
+
+struct MemPoolInner {
+  free:     HashMap<usize, Vec<PoolBlock>>,
+  stats:    MemPoolStats,
+}
+
+impl MemPoolInner {
+  fn release(&mut self, class: usize, block: PoolBlock) {
+    unsafe { dealloc(block.ptr, Layout::from_size_align_unchecked(class, POOL_ALIGN)) };
+    self.stats.bytes_held -= class;
+  }
+}
+
+impl Drop for MemPoolInner {
+  fn drop(&mut self) {
+    let free: Vec<_> = self.free.drain().collect();
+    for (class, blocks) in free.into_iter() {
+      for block in blocks.into_iter() {
+        self.release(class, block);
+      }
+    }
+  }
+}
+
+/// A caching allocator for buffers of repeated sizes. Buffers are bucketed
+/// by power-of-two size class and returned to their bucket when dropped.
+/// `MemPool` is a cheap handle; clones refer to the same pool.
+#[derive(Clone)]
+pub struct MemPool {
+  inner:    Arc<Mutex<MemPoolInner>>,
+}
+
+impl Default for MemPool {
+  fn default() -> Self {
+    MemPool::new()
+  }
+}
+
+impl MemPool {
+  pub fn new() -> Self {
+    MemPool{
+      inner:    Arc::new(Mutex::new(MemPoolInner{
+        free:   HashMap::new(),
+        stats:  MemPoolStats::default(),
+      })),
+    }
+  }
+
+  /// Returns a handle to this thread's default pool.
+  pub fn thread_default() -> Self {
+    DEFAULT_POOL.with(|pool| pool.clone())
+  }
+
+  pub fn stats(&self) -> MemPoolStats {
+    self.inner.lock().unwrap().stats
+  }
+
+  /// Frees every cached block.
+  pub fn trim(&self) {
+    self.trim_to(0);
+  }
+
+  /// Frees cached blocks, largest size class first, until at most
+  /// `max_bytes_held` bytes remain cached.
+  pub fn trim_to(&self, max_bytes_held: usize) {
+    let mut inner = self.inner.lock().unwrap();
+    let mut classes: Vec<_> = inner.free.keys().cloned().collect();
+    classes.sort();
+    for &class in classes.iter().rev() {
+      while inner.stats.bytes_held > max_bytes_held {
+        let block = match inner.free.get_mut(&class).and_then(|blocks| blocks.pop()) {
+          None => break,
+          Some(block) => block,
+        };
+        inner.release(class, block);
+      }
+    }
+    inner.free.retain(|_, blocks| !blocks.is_empty());
+  }
+
+  pub unsafe fn alloc<T>(&self, len: usize) -> PoolMem<T> where T: Copy {
+    assert!(align_of::<T>() <= POOL_ALIGN);
+    let class = match len.checked_mul(size_of::<T>()).and_then(size_class) {
+      None => panic!("MemPool: allocation too large: len: {} elem size: {}", len, size_of::<T>()),
+      Some(class) => class,
+    };
+    let mut inner = self.inner.lock().unwrap();
+    let cached = inner.free.get_mut(&class).and_then(|blocks| blocks.pop());
+    let ptr = match cached {
+      Some(block) => {
+        inner.stats.hits += 1;
+        inner.stats.bytes_held -= class;
+        block.ptr
+      }
+      None => {
+        inner.stats.misses += 1;
+        let layout = Layout::from_size_align(class, POOL_ALIGN).unwrap();
+        let p = alloc(layout);
+        if p.is_null() {
+          handle_alloc_error(layout);
+        }
+        p
+      }
+    };
+    inner.stats.bytes_in_use += class;
+    PoolMem{
+      buf:      ptr as *mut T,
+      len:      len,
+      class:    class,
+      pool:     self.clone(),
+      acct:     MemAccount::new(class),
+    }
+  }
+
+  fn put(&self, ptr: *mut u8, class: usize) {
+    let mut inner = self.inner.lock().unwrap();
+    inner.stats.bytes_in_use -= class;
+    inner.stats.bytes_held += class;
+    inner.free.entry(class).or_insert_with(|| vec![]).push(PoolBlock{ptr});
+  }
+}
+
+/// Memory drawn from a `MemPool`, which returns to the pool when dropped.
+pub struct PoolMem<T> where T: Copy {
+  buf:      *mut T,
+  len:      usize,
+  class:    usize,
+  pool:     MemPool,
+  acct:     MemAccount,
+}
+
+unsafe impl<T> Send for PoolMem<T> where T: Copy + Send {}
+unsafe impl<T> Sync for PoolMem<T> where T: Copy + Sync {}
+
+impl<T> Drop for PoolMem<T> where T: Copy {
+  fn drop(&mut self) {
+    self.pool.put(self.buf as *mut u8, self.class);
+  }
+}
+
+impl<T> PoolMem<T> where T: Copy {
+  pub fn pool(&self) -> &MemPool {
+    &self.pool
+  }
+}
+
+impl<T> TagMem for PoolMem<T> where T: Copy {
+  fn tag(&self) -> &'static str {
+    self.acct.tag()
+  }
+
+  fn set_tag(&mut self, tag: &'static str) {
+    self.acct.set_tag(tag);
+  }
+}
+
+impl<T> ReadOnlyMem<T> for PoolMem<T> where T: Copy {
+  unsafe fn as_ptr(&self) -> *const T {
+    self.buf
+  }
+
+  fn as_slice(&self) -> &[T] {
+    unsafe { from_raw_parts(self.buf, self.len) }
+  }
+
+  fn as_bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts(self.buf as *const u8, self.len * size_of::<T>()) }
+  }
+}
+
+impl<T> Mem<T> for PoolMem<T> where T: Copy {
+  unsafe fn as_mut_ptr(&mut self) -> *mut T {
+    self.buf
+  }
+
+  fn as_mut_slice(&mut self) -> &mut [T] {
+    unsafe { from_raw_parts_mut(self.buf, self.len) }
+  }
+
+  fn as_mut_bytes(&mut self) -> &mut [u8] {
+    unsafe { from_raw_parts_mut(self.buf as *mut u8, self.len * size_of::<T>()) }
+  }
+}
+
+impl<Idx, T> MemArray<Idx, T, PoolMem<T>> where Idx: ArrayIndex, T: ZeroBits {
+  /// Like `MemArray::zeros`, but draws the memory from this thread's default
+  /// pool.
+  pub fn zeros_pooled(size: Idx) -> Self {
+    MemArray::zeros_in_pool(size, &MemPool::thread_default())
+  }
+
+  pub fn zeros_in_pool(size: Idx, pool: &MemPool) -> Self {
+    let mem = unsafe { pool.alloc::<T>(size.flat_len()) };
+    // Pooled blocks may hold stale data, zero it using memset.
+    unsafe { write_bytes::<T>(mem.buf, 0, mem.len) };
+    MemArray::with_memory(size, mem)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use arrayidx::{Index1d};
+
+  type PoolArray1d<T> = MemArray<Index1d, T, PoolMem<T>>;
+
+  #[test]
+  fn pool_hits_and_misses() {
+    let pool = MemPool::new();
+    {
+      let a = PoolArray1d::<f32>::zeros_in_pool(100, &pool);
+      assert_eq!(a.size, 100);
+      let stats = pool.stats();
+      assert_eq!(stats.hits, 0);
+      assert_eq!(stats.misses, 1);
+      assert_eq!(stats.bytes_held, 0);
+      assert_eq!(stats.bytes_in_use, 512);
+    }
+    let stats = pool.stats();
+    assert_eq!(stats.bytes_held, 512);
+    assert_eq!(stats.bytes_in_use, 0);
+    {
+      // Same size class, so the cached block is reused.
+      let _b = PoolArray1d::<f32>::zeros_in_pool(128, &pool);
+      // A different size class misses.
+      let _c = PoolArray1d::<f32>::zeros_in_pool(4, &pool);
+      let stats = pool.stats();
+      assert_eq!(stats.hits, 1);
+      assert_eq!(stats.misses, 2);
+      assert_eq!(stats.bytes_held, 0);
+      assert_eq!(stats.bytes_in_use, 512 + 64);
+    }
+    let stats = pool.stats();
+    assert_eq!(stats.bytes_held, 512 + 64);
+    assert_eq!(stats.bytes_in_use, 0);
+  }
+
+  #[test]
+  fn pooled_memory_is_zeroed() {
+    let pool = MemPool::new();
+    {
+      let mut a = PoolArray1d::<u32>::zeros_in_pool(16, &pool);
+      for x in a.memory_mut().as_mut_slice().iter_mut() {
+        *x = 0xdead;
+      }
+    }
+    let b = PoolArray1d::<u32>::zeros_in_pool(16, &pool);
+    assert_eq!(pool.stats().hits, 1);
+    assert!(b.memory().as_slice().iter().all(|&x| x == 0));
+  }
+
+  #[test]
+  fn pool_trim() {
+    let pool = MemPool::new();
+    {
+      let _a = PoolArray1d::<u8>::zeros_in_pool(64, &pool);
+      let _b = PoolArray1d::<u8>::zeros_in_pool(256, &pool);
+      let _c = PoolArray1d::<u8>::zeros_in_pool(1024, &pool);
+    }
+    assert_eq!(pool.stats().bytes_held, 64 + 256 + 1024);
+    pool.trim_to(400);
+    assert_eq!(pool.stats().bytes_held, 64 + 256);
+    pool.trim();
+    assert_eq!(pool.stats().bytes_held, 0);
+    let _d = PoolArray1d::<u8>::zeros_in_pool(64, &pool);
+    assert_eq!(pool.stats().misses, 4);
+  }
+
+  #[test]
+  fn size_classes() {
+    assert_eq!(size_class(0), Some(64));
+    assert_eq!(size_class(64), Some(64));
+    assert_eq!(size_class(65), Some(128));
+    assert_eq!(size_class(usize::max_value() / 2 + 2), None);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemPool: allocation too large")]
+  fn alloc_too_large() {
+    let pool = MemPool::default();
+    let _ = PoolArray1d::<u64>::zeros_in_pool(usize::max_value() / 4, &pool);
+  }
+}
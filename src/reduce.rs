@@ -0,0 +1,523 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+// Reductions over all elements return a scalar. Reductions along an axis
+// return a new packed array of size `NewIdx`, which either drops the reduced
+// axis or, if it has the same rank as the input, keeps it with length 1.
+//
+// As in numpy, `min`, `max` and their arg variants propagate NaNs (the first
+// NaN wins), while the `nan*` variants skip them; a lane of only NaNs still
+// reduces to its first NaN.
+
+use ::{Mem, MemArray, MemArrayView, ReadOnlyMem, ZeroBits, for_each_flat_offset};
+
+use arrayidx::*;
+
+/// Element types supported by the reductions.
+pub trait ReduceElem: ZeroBits + PartialOrd {
+  /// Sums `iter`. Integer sums wrap on overflow, in every build profile.
+  /// Floats use compensated (Kahan) summation, so that long sums stay
+  /// accurate.
+  fn sum<I>(iter: I) -> Self where I: Iterator<Item=Self>;
+  fn is_nan(self) -> bool;
+}
+
+/// Element types which have a mean, i.e. floats.
+pub trait MeanElem: ReduceElem {
+  fn div_len(self, n: usize) -> Self;
+}
+
+macro_rules! impl_reduce_elem_int {
+  ($($t:ty),*) => {
+    $(
+      impl ReduceElem for $t {
+        fn sum<I>(iter: I) -> $t where I: Iterator<Item=$t> {
+          iter.fold(0, |s: $t, x| s.wrapping_add(x))
+        }
+
+        fn is_nan(self) -> bool {
+          false
+        }
+      }
+    )*
+  };
+}
+
+impl_reduce_elem_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+macro_rules! impl_reduce_elem_float {
+  ($($t:ty),*) => {
+    $(
+      impl ReduceElem for $t {
+        fn sum<I>(iter: I) -> $t where I: Iterator<Item=$t> {
+          let mut s: $t = 0.0;
+          let mut c: $t = 0.0;
+          for x in iter {
+            let y = x - c;
+            let t = s + y;
+            // The compensation is meaningless once the sum is inf or NaN.
+            c = if t.is_finite() { (t - s) - y } else { 0.0 };
+            s = t;
+          }
+          s
+        }
+
+        fn is_nan(self) -> bool {
+          <$t>::is_nan(self)
+        }
+      }
+
+      impl MeanElem for $t {
+        fn div_len(self, n: usize) -> $t {
+          self / n as $t
+        }
+      }
+    )*
+  };
+}
+
+impl_reduce_elem_float!(f32, f64);
+
+// Returns the position and value of the element of `iter` which is `better`
+// than all the others, keeping the first of equal elements; see above for
+// NaNs.
+fn extremum<T, I>(iter: I, skip_nan: bool, better: fn(T, T) -> bool) -> Option<(usize, T)>
+where T: ReduceElem, I: Iterator<Item=T>,
+{
+  let mut best: Option<(usize, T)> = None;
+  for (i, x) in iter.enumerate() {
+    match best {
+      None => best = Some((i, x)),
+      Some((_, y)) => {
+        if skip_nan {
+          if !x.is_nan() && (y.is_nan() || better(x, y)) {
+            best = Some((i, x));
+          }
+        } else {
+          if y.is_nan() {
+            break;
+          }
+          if x.is_nan() || better(x, y) {
+            best = Some((i, x));
+          }
+        }
+      }
+    }
+  }
+  best
+}
+
+fn less<T: PartialOrd>(x: T, y: T) -> bool {
+  x < y
+}
+
+fn greater<T: PartialOrd>(x: T, y: T) -> bool {
+  x > y
+}
+
+fn sum<T, I>(iter: I, skip_nan: bool) -> T where T: ReduceElem, I: Iterator<Item=T> {
+  T::sum(iter.filter(|x| !(skip_nan && x.is_nan())))
+}
+
+fn mean<T, I>(iter: I, skip_nan: bool) -> T where T: MeanElem, I: Iterator<Item=T> {
+  let mut n = 0;
+  let s = T::sum(iter.filter(|x| !(skip_nan && x.is_nan())).inspect(|_| n += 1));
+  s.div_len(n)
+}
+
+// Converts a position in column-major order into an index.
+fn unflatten<Idx>(mut pos: usize, size: &Idx) -> Idx where Idx: ArrayIndex {
+  let mut idx = size.to_nd();
+  for i in idx.iter_mut() {
+    let s = *i;
+    *i = pos % s;
+    pos /= s;
+  }
+  Idx::from_nd(idx)
+}
+
+// The elements of a view along one axis, starting at a flat offset.
+struct Lane<'a, T> where T: 'a {
+  mem:      &'a [T],
+  offset:   usize,
+  stride:   usize,
+  len:      usize,
+}
+
+impl<'a, T> Iterator for Lane<'a, T> where T: Copy {
+  type Item = T;
+
+  fn next(&mut self) -> Option<T> {
+    if self.len == 0 {
+      return None;
+    }
+    let x = self.mem[self.offset];
+    self.offset = self.offset.wrapping_add(self.stride);
+    self.len -= 1;
+    Some(x)
+  }
+}
+
+// Applies `f` to each lane of `view` along `axis`, collecting the results in
+// the order of the remaining axes.
+fn reduce_axis<Idx, NewIdx, T, U, F>(view: &MemArrayView<Idx, T>, axis: usize, name: &str, nonempty: bool, mut f: F) -> MemArray<NewIdx, U>
+where Idx: ArrayIndex, NewIdx: ArrayIndex, T: Copy + 'static, U: ZeroBits, F: FnMut(Lane<T>) -> U,
+{
+  let (mut size, mut stride) = (view.size.to_nd(), view.stride.to_nd());
+  let nd = NewIdx::zero().to_nd().len();
+  assert!(axis < size.len(),
+      "MemArrayView::{}: axis out of bounds: axis: {} ndim: {}", name, axis, size.len());
+  assert!(nd == size.len() || nd + 1 == size.len(),
+      "MemArrayView::{}: rank mismatch: size: {:?} new ndim: {}", name, size, nd);
+  let len = size[axis];
+  let axis_stride = stride[axis];
+  size.remove(axis);
+  stride.remove(axis);
+  assert!(len > 0 || !nonempty || size.iter().any(|&s| s == 0),
+      "MemArrayView::{}: empty axis: axis: {} size: {:?}", name, axis, view.size);
+  let mut new_size = size.clone();
+  if nd == new_size.len() + 1 {
+    new_size.insert(axis, 1);
+  }
+  let mut dst = MemArray::zeros(NewIdx::from_nd(new_size));
+  {
+    let src = view.mem.as_slice();
+    let dst = dst.memory_mut().as_mut_slice();
+    let mut i = 0;
+    for_each_flat_offset(&size, &stride, view.offset, |off| {
+      dst[i] = f(Lane{mem: src, offset: off, stride: axis_stride, len: len});
+      i += 1;
+    });
+  }
+  dst
+}
+
+impl<'a, Idx, T> MemArrayView<'a, Idx, T> where Idx: ArrayIndex, T: ReduceElem + 'static {
+  pub fn sum(&self) -> T {
+    sum(self.iter().cloned(), false)
+  }
+
+  /// Like `sum`, skipping NaNs.
+  pub fn nansum(&self) -> T {
+    sum(self.iter().cloned(), true)
+  }
+
+  /// The mean of all elements, which is NaN if there are none.
+  pub fn mean(&self) -> T where T: MeanElem {
+    mean(self.iter().cloned(), false)
+  }
+
+  pub fn nanmean(&self) -> T where T: MeanElem {
+    mean(self.iter().cloned(), true)
+  }
+
+  /// The least element, or `None` if the view is empty.
+  pub fn min(&self) -> Option<T> {
+    extremum(self.iter().cloned(), false, less).map(|(_, x)| x)
+  }
+
+  pub fn nanmin(&self) -> Option<T> {
+    extremum(self.iter().cloned(), true, less).map(|(_, x)| x)
+  }
+
+  pub fn max(&self) -> Option<T> {
+    extremum(self.iter().cloned(), false, greater).map(|(_, x)| x)
+  }
+
+  pub fn nanmax(&self) -> Option<T> {
+    extremum(self.iter().cloned(), true, greater).map(|(_, x)| x)
+  }
+
+  /// The index of the first least element, or `None` if the view is empty.
+  pub fn argmin(&self) -> Option<Idx> {
+    extremum(self.iter().cloned(), false, less).map(|(i, _)| unflatten(i, &self.size))
+  }
+
+  pub fn nanargmin(&self) -> Option<Idx> {
+    extremum(self.iter().cloned(), true, less).map(|(i, _)| unflatten(i, &self.size))
+  }
+
+  pub fn argmax(&self) -> Option<Idx> {
+    extremum(self.iter().cloned(), false, greater).map(|(i, _)| unflatten(i, &self.size))
+  }
+
+  pub fn nanargmax(&self) -> Option<Idx> {
+    extremum(self.iter().cloned(), true, greater).map(|(i, _)| unflatten(i, &self.size))
+  }
+
+  pub fn sum_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "sum_axis", false, |lane| sum(lane, false))
+  }
+
+  pub fn nansum_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "nansum_axis", false, |lane| sum(lane, true))
+  }
+
+  pub fn mean_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex, T: MeanElem {
+    reduce_axis(self, axis, "mean_axis", false, |lane| mean(lane, false))
+  }
+
+  pub fn nanmean_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex, T: MeanElem {
+    reduce_axis(self, axis, "nanmean_axis", false, |lane| mean(lane, true))
+  }
+
+  /// The least element along `axis`. Panics if the axis is empty.
+  pub fn min_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "min_axis", true, |lane| extremum(lane, false, less).unwrap().1)
+  }
+
+  pub fn nanmin_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "nanmin_axis", true, |lane| extremum(lane, true, less).unwrap().1)
+  }
+
+  pub fn max_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "max_axis", true, |lane| extremum(lane, false, greater).unwrap().1)
+  }
+
+  pub fn nanmax_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "nanmax_axis", true, |lane| extremum(lane, true, greater).unwrap().1)
+  }
+
+  /// The position along `axis` of the first least element. Panics if the
+  /// axis is empty.
+  pub fn argmin_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "argmin_axis", true, |lane| extremum(lane, false, less).unwrap().0)
+  }
+
+  pub fn nanargmin_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "nanargmin_axis", true, |lane| extremum(lane, true, less).unwrap().0)
+  }
+
+  pub fn argmax_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "argmax_axis", true, |lane| extremum(lane, false, greater).unwrap().0)
+  }
+
+  pub fn nanargmax_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    reduce_axis(self, axis, "nanargmax_axis", true, |lane| extremum(lane, true, greater).unwrap().0)
+  }
+}
+
+impl<Idx, T, M> MemArray<Idx, T, M> where Idx: ArrayIndex, T: ReduceElem + 'static, M: ReadOnlyMem<T> {
+  pub fn sum(&self) -> T {
+    self.as_view().sum()
+  }
+
+  pub fn nansum(&self) -> T {
+    self.as_view().nansum()
+  }
+
+  pub fn mean(&self) -> T where T: MeanElem {
+    self.as_view().mean()
+  }
+
+  pub fn nanmean(&self) -> T where T: MeanElem {
+    self.as_view().nanmean()
+  }
+
+  pub fn min(&self) -> Option<T> {
+    self.as_view().min()
+  }
+
+  pub fn nanmin(&self) -> Option<T> {
+    self.as_view().nanmin()
+  }
+
+  pub fn max(&self) -> Option<T> {
+    self.as_view().max()
+  }
+
+  pub fn nanmax(&self) -> Option<T> {
+    self.as_view().nanmax()
+  }
+
+  pub fn argmin(&self) -> Option<Idx> {
+    self.as_view().argmin()
+  }
+
+  pub fn nanargmin(&self) -> Option<Idx> {
+    self.as_view().nanargmin()
+  }
+
+  pub fn argmax(&self) -> Option<Idx> {
+    self.as_view().argmax()
+  }
+
+  pub fn nanargmax(&self) -> Option<Idx> {
+    self.as_view().nanargmax()
+  }
+
+  pub fn sum_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().sum_axis(axis)
+  }
+
+  pub fn nansum_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().nansum_axis(axis)
+  }
+
+  pub fn mean_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex, T: MeanElem {
+    self.as_view().mean_axis(axis)
+  }
+
+  pub fn nanmean_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex, T: MeanElem {
+    self.as_view().nanmean_axis(axis)
+  }
+
+  pub fn min_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().min_axis(axis)
+  }
+
+  pub fn nanmin_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().nanmin_axis(axis)
+  }
+
+  pub fn max_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().max_axis(axis)
+  }
+
+  pub fn nanmax_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, T> where NewIdx: ArrayIndex {
+    self.as_view().nanmax_axis(axis)
+  }
+
+  pub fn argmin_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    self.as_view().argmin_axis(axis)
+  }
+
+  pub fn nanargmin_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    self.as_view().nanargmin_axis(axis)
+  }
+
+  pub fn argmax_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    self.as_view().argmax_axis(axis)
+  }
+
+  pub fn nanargmax_axis<NewIdx>(&self, axis: usize) -> MemArray<NewIdx, usize> where NewIdx: ArrayIndex {
+    self.as_view().nanargmax_axis(axis)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ::{MemArray1d, MemArray2d};
+
+  use std::f32;
+
+  fn iota(size: [usize; 2]) -> MemArray2d<f32> {
+    let mut a = MemArray2d::zeros(size);
+    for j in 0 .. size[1] {
+      for i in 0 .. size[0] {
+        a.set([i, j], (i + 10 * j) as f32);
+      }
+    }
+    a
+  }
+
+  #[test]
+  fn int_sum_wraps() {
+    let mut a = MemArray1d::<u8>::zeros(3);
+    a.map_inplace(|x| *x = 200);
+    assert_eq!(a.sum(), 88);
+    let mut b = MemArray1d::<i16>::zeros(2);
+    b.map_inplace(|x| *x = i16::max_value());
+    assert_eq!(b.sum(), -2);
+  }
+
+  #[test]
+  fn float_sum_compensated() {
+    let mut a = MemArray1d::<f32>::zeros(10_000_000);
+    a.map_inplace(|x| *x = 0.1);
+    assert!((a.sum() - 1.0e6).abs() < 1.0);
+    let mut b = MemArray1d::<f64>::zeros(2);
+    b.map_inplace(|x| *x = ::std::f64::INFINITY);
+    assert_eq!(b.sum(), ::std::f64::INFINITY);
+  }
+
+  #[test]
+  fn full_reductions() {
+    let a = iota([3, 4]);
+    assert_eq!(a.sum(), 192.0);
+    assert_eq!(a.mean(), 16.0);
+    assert_eq!(a.min(), Some(0.0));
+    assert_eq!(a.max(), Some(32.0));
+    assert_eq!(a.argmin(), Some([0, 0]));
+    assert_eq!(a.argmax(), Some([2, 3]));
+    assert_eq!(MemArray1d::<f32>::zeros(0).max(), None);
+    assert!(MemArray1d::<f32>::zeros(0).mean().is_nan());
+  }
+
+  #[test]
+  fn reductions_with_nan() {
+    let mut a = iota([3, 4]);
+    a.set([1, 1], f32::NAN);
+    a.set([2, 2], f32::NAN);
+    assert!(a.sum().is_nan());
+    assert!(a.mean().is_nan());
+    assert!(a.min().unwrap().is_nan());
+    assert!(a.max().unwrap().is_nan());
+    assert_eq!(a.argmin(), Some([1, 1]));
+    assert_eq!(a.argmax(), Some([1, 1]));
+    assert_eq!(a.nansum(), 192.0 - 11.0 - 22.0);
+    assert_eq!(a.nanmean(), (192.0 - 11.0 - 22.0) / 10.0);
+    assert_eq!(a.nanmin(), Some(0.0));
+    assert_eq!(a.nanmax(), Some(32.0));
+    assert_eq!(a.nanargmax(), Some([2, 3]));
+    let mut b = MemArray1d::<f32>::zeros(3);
+    b.map_inplace(|x| *x = f32::NAN);
+    assert!(b.nanmin().unwrap().is_nan());
+    assert_eq!(b.nanargmin(), Some(0));
+    assert!(b.nanmean().is_nan());
+  }
+
+  #[test]
+  fn axis_reductions() {
+    let mut a = iota([3, 4]);
+    let s: MemArray1d<f32> = a.sum_axis(0);
+    assert_eq!(s.size, 4);
+    assert_eq!(s[1], 33.0);
+    let s: MemArray2d<f32> = a.sum_axis(1);
+    assert_eq!(s.size, [3, 1]);
+    assert_eq!(s[[2, 0]], 68.0);
+    a.set([1, 1], f32::NAN);
+    let m: MemArray1d<f32> = a.max_axis(0);
+    assert!(m[1].is_nan());
+    assert_eq!(m[2], 22.0);
+    let m: MemArray1d<f32> = a.nanmax_axis(0);
+    assert_eq!(m[1], 12.0);
+    let i: MemArray1d<usize> = a.argmin_axis(0);
+    assert_eq!(i[1], 1);
+    let i: MemArray1d<usize> = a.nanargmin_axis(0);
+    assert_eq!(i[1], 0);
+    let n: MemArray2d<f32> = a.nanmean_axis(0);
+    assert_eq!(n.size, [1, 4]);
+    assert_eq!(n[[0, 1]], 11.0);
+  }
+
+  #[test]
+  fn strided_reductions() {
+    let a = iota([3, 4]);
+    let v = a.as_view().slice::<[usize; 2]>(&[::SliceSpec::all().rev(), (1 ..).into()]);
+    assert_eq!(v.sum(), 189.0);
+    assert_eq!(v.argmin(), Some([2, 0]));
+    let i: MemArray1d<usize> = v.argmax_axis(0);
+    assert_eq!(i[0], 0);
+  }
+
+  #[test]
+  #[should_panic(expected = "MemArrayView::min_axis: empty axis")]
+  fn empty_axis() {
+    let a = MemArray2d::<f32>::zeros([0, 3]);
+    let _: MemArray1d<f32> = a.min_axis(0);
+  }
+}
@@ -0,0 +1,493 @@
+/*
+Copyright 2017-2018 Peter Jin
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use ::{Mem, MemArray, ReadOnlyMem, ZeroBits};
+use accounting::{MemAccount, TagMem};
+use io::{NpyDtype, ToNpyDtypeDesc};
+
+use arrayidx::{ArrayIndex};
+use libc;
+
+use std::ffi::{CString};
+use std::marker::{PhantomData};
+use std::mem::{size_of, zeroed};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr::{null_mut, read, write};
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+
+const SHM_MAGIC:      u64 = 0x5941_5252_414d_454d; // "MEMARRAY"
+const SHM_VERSION:    u32 = 1;
+const SHM_MAX_DIM:    usize = 8;
+const SHM_HEADER_LEN: usize = 128;
+
+fn dtype_to_code(dtype: NpyDtype) -> u32 {
+  match dtype {
+    NpyDtype::Float32 => 1,
+    NpyDtype::Float64 => 2,
+    NpyDtype::Int8    => 3,
+    NpyDtype::Int16   => 4,
+    NpyDtype::Int32   => 5,
+    NpyDtype::Int64   => 6,
+    NpyDtype::UInt8   => 7,
+    NpyDtype::UInt16  => 8,
+    NpyDtype::UInt32  => 9,
+    NpyDtype::UInt64  => 10,
+  }
+}
+
+fn code_to_dtype(code: u32) -> Option<NpyDtype> {
+  match code {
+    1  => Some(NpyDtype::Float32),
+    2  => Some(NpyDtype::Float64),
+    3  => Some(NpyDtype::Int8),
+    4  => Some(NpyDtype::Int16),
+    5  => Some(NpyDtype::Int32),
+    6  => Some(NpyDtype::Int64),
+    7  => Some(NpyDtype::UInt8),
+    8  => Some(NpyDtype::UInt16),
+    9  => Some(NpyDtype::UInt32),
+    10 => Some(NpyDtype::UInt64),
+    _  => None,
+  }
+}
+
+/// The header stored at the start of every shared memory object, ahead of
+/// the array data. It records enough to rebuild the `MemArray` on the
+/// attaching side.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ShmHeader {
+  magic:    u64,
+  version:  u32,
+  dtype:    u32,
+  elem_sz:  u32,
+  ndim:     u32,
+  size:     [u64; SHM_MAX_DIM],
+  len:      u64,
+}
+
+// The array data starts at `SHM_HEADER_LEN`, so the header must fit before it.
+const _: () = assert!(size_of::<ShmHeader>() <= SHM_HEADER_LEN);
+
+impl ShmHeader {
+  fn new<Idx, T>(size: &Idx) -> Result<Self, ()> where Idx: ArrayIndex, T: ToNpyDtypeDesc {
+    let nd_size = size.to_nd();
+    if nd_size.len() > SHM_MAX_DIM {
+      return Err(());
+    }
+    let mut header: ShmHeader = unsafe { zeroed() };
+    header.magic = SHM_MAGIC;
+    header.version = SHM_VERSION;
+    header.dtype = dtype_to_code(T::to_npy_dtype_desc().dtype);
+    header.elem_sz = size_of::<T>() as u32;
+    header.ndim = nd_size.len() as u32;
+    for (d, &s) in nd_size.iter().enumerate() {
+      header.size[d] = s as u64;
+    }
+    header.len = nd_size.iter().try_fold(1usize, |acc, &s| acc.checked_mul(s)).ok_or(())? as u64;
+    if header.map_len::<T>().is_none() {
+      return Err(());
+    }
+    Ok(header)
+  }
+
+  // The length in bytes of a mapping holding the header and the data, or
+  // `None` if it overflows. The header may come from another process, so
+  // this must not trust its fields.
+  fn map_len<T>(&self) -> Option<usize> {
+    if self.len > usize::max_value() as u64 {
+      return None;
+    }
+    (self.len as usize).checked_mul(size_of::<T>())?.checked_add(SHM_HEADER_LEN)
+  }
+
+  fn check<T>(&self) -> Result<(), ()> where T: ToNpyDtypeDesc {
+    if self.magic != SHM_MAGIC || self.version != SHM_VERSION {
+      return Err(());
+    }
+    if self.code_dtype() != Some(T::to_npy_dtype_desc().dtype) {
+      return Err(());
+    }
+    if self.elem_sz as usize != size_of::<T>() {
+      return Err(());
+    }
+    if self.ndim as usize > SHM_MAX_DIM {
+      return Err(());
+    }
+    let flat_len = self.size[ .. self.ndim as usize].iter().try_fold(1u64, |acc, &s| acc.checked_mul(s));
+    if flat_len != Some(self.len) || self.map_len::<T>().is_none() {
+      return Err(());
+    }
+    if self.size[ .. self.ndim as usize].iter().any(|&s| s > usize::max_value() as u64) {
+      return Err(());
+    }
+    Ok(())
+  }
+
+  fn code_dtype(&self) -> Option<NpyDtype> {
+    code_to_dtype(self.dtype)
+  }
+
+  pub fn dtype(&self) -> NpyDtype {
+    self.code_dtype().unwrap()
+  }
+
+  pub fn nd_size(&self) -> Vec<usize> {
+    self.size[ .. self.ndim as usize].iter().map(|&s| s as usize).collect()
+  }
+
+  pub fn flat_len(&self) -> usize {
+    self.len as usize
+  }
+}
+
+/// Memory backed by a POSIX shared memory object or a memfd, mapped with
+/// `MAP_SHARED` so that writes are visible to every process that has the
+/// object mapped. The element type must have a numpy dtype, i.e. be one of
+/// the fixed-size integer or float types; see `ToNpyDtypeDesc`.
+///
+/// Each `ShmMem` assumes that it is the only one accessing its mapping, as
+/// `as_mut_slice` hands out a `&mut [T]`. Attaching to an object which is
+/// already mapped (by this or another process) is therefore `unsafe`; see
+/// `open_named`.
+pub struct ShmMem<T> where T: Copy {
+  fd:       RawFd,
+  base:     *mut u8,
+  map_len:  usize,
+  header:   ShmHeader,
+  acct:     MemAccount,
+  _mrk:     PhantomData<T>,
+}
+
+unsafe impl<T> Send for ShmMem<T> where T: Copy + Send {}
+unsafe impl<T> Sync for ShmMem<T> where T: Copy + Sync {}
+
+impl<T> Drop for ShmMem<T> where T: Copy {
+  fn drop(&mut self) {
+    assert!(!self.base.is_null());
+    unsafe {
+      libc::munmap(self.base as *mut libc::c_void, self.map_len);
+      libc::close(self.fd);
+    }
+    self.base = null_mut();
+  }
+}
+
+unsafe fn map_shared(fd: RawFd, map_len: usize) -> Result<*mut u8, ()> {
+  let p = libc::mmap(
+      null_mut(),
+      map_len,
+      libc::PROT_READ | libc::PROT_WRITE,
+      libc::MAP_SHARED,
+      fd,
+      0,
+  );
+  if p == libc::MAP_FAILED {
+    return Err(());
+  }
+  Ok(p as *mut u8)
+}
+
+impl<T> ShmMem<T> where T: ToNpyDtypeDesc + ZeroBits {
+  /// Creates a new named POSIX shared memory object (via `shm_open`) large
+  /// enough for an array of size `size`. The data is zero-initialized. Fails
+  /// if an object of the same name already exists.
+  pub fn create_named<Idx>(name: &str, size: &Idx) -> Result<Self, ()> where Idx: ArrayIndex {
+    let c_name = CString::new(name).map_err(|_| ())?;
+    let fd = unsafe { libc::shm_open(
+        c_name.as_ptr(),
+        libc::O_RDWR | libc::O_CREAT | libc::O_EXCL,
+        0o600,
+    ) };
+    if fd < 0 {
+      return Err(());
+    }
+    match unsafe { ShmMem::create_fd(fd, size) } {
+      Err(_) => {
+        unsafe { libc::shm_unlink(c_name.as_ptr()) };
+        Err(())
+      }
+      Ok(mem) => Ok(mem),
+    }
+  }
+
+  /// Creates a new anonymous memfd large enough for an array of size `size`.
+  /// The data is zero-initialized. The file descriptor is not close-on-exec,
+  /// so it may be inherited by child processes as well as sent over a unix
+  /// socket.
+  #[cfg(target_os = "linux")]
+  pub fn create_memfd<Idx>(name: &str, size: &Idx) -> Result<Self, ()> where Idx: ArrayIndex {
+    let c_name = CString::new(name).map_err(|_| ())?;
+    let fd = unsafe { libc::memfd_create(c_name.as_ptr(), 0) };
+    if fd < 0 {
+      return Err(());
+    }
+    unsafe { ShmMem::create_fd(fd, size) }
+  }
+
+  /// Attaches to an existing named POSIX shared memory object created by
+  /// `create_named`.
+  ///
+  /// # Safety
+  ///
+  /// The object is usually mapped elsewhere as well, e.g. by the `ShmMem`
+  /// returned from `create_named`, or by an earlier `open_named` in this or
+  /// another process. The caller must ensure that while a slice from one of
+  /// the mappings is alive, the others do not write to the same elements (or
+  /// read them, if the slice is mutable).
+  pub unsafe fn open_named(name: &str) -> Result<Self, ()> {
+    let c_name = CString::new(name).map_err(|_| ())?;
+    let fd = libc::shm_open(c_name.as_ptr(), libc::O_RDWR, 0);
+    if fd < 0 {
+      return Err(());
+    }
+    ShmMem::attach_fd(fd)
+  }
+
+  /// Attaches to an existing shared memory object given its file descriptor,
+  /// e.g. one received from another process. Ownership of `fd` is taken even
+  /// if attaching fails.
+  ///
+  /// # Safety
+  ///
+  /// `fd` must be an open file descriptor which is not used elsewhere, and
+  /// the mappings of the object must not alias as described in `open_named`.
+  pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self, ()> {
+    ShmMem::attach_fd(fd)
+  }
+
+  unsafe fn create_fd<Idx>(fd: RawFd, size: &Idx) -> Result<Self, ()> where Idx: ArrayIndex {
+    let header = match ShmHeader::new::<Idx, T>(size) {
+      Err(_) => {
+        libc::close(fd);
+        return Err(());
+      }
+      Ok(header) => header,
+    };
+    let map_len = match header.map_len::<T>() {
+      Some(map_len) if map_len as u64 <= libc::off_t::max_value() as u64 => map_len,
+      _ => {
+        libc::close(fd);
+        return Err(());
+      }
+    };
+    // The extended region of the file reads back as zeros.
+    if libc::ftruncate(fd, map_len as libc::off_t) != 0 {
+      libc::close(fd);
+      return Err(());
+    }
+    let base = match map_shared(fd, map_len) {
+      Err(_) => {
+        libc::close(fd);
+        return Err(());
+      }
+      Ok(base) => base,
+    };
+    write(base as *mut ShmHeader, header);
+    Ok(ShmMem{
+      fd:       fd,
+      base:     base,
+      map_len:  map_len,
+      header:   header,
+      acct:     MemAccount::new(map_len),
+      _mrk:     PhantomData,
+    })
+  }
+
+  unsafe fn attach_fd(fd: RawFd) -> Result<Self, ()> {
+    let mut stat: libc::stat = zeroed();
+    if libc::fstat(fd, &mut stat) != 0 || (stat.st_size as usize) < SHM_HEADER_LEN {
+      libc::close(fd);
+      return Err(());
+    }
+    let map_len = stat.st_size as usize;
+    let base = match map_shared(fd, map_len) {
+      Err(_) => {
+        libc::close(fd);
+        return Err(());
+      }
+      Ok(base) => base,
+    };
+    let header = read(base as *const ShmHeader);
+    let mem = ShmMem{
+      fd:       fd,
+      base:     base,
+      map_len:  map_len,
+      header:   header,
+      acct:     MemAccount::new(map_len),
+      _mrk:     PhantomData,
+    };
+    mem.header.check::<T>()?;
+    match mem.header.map_len::<T>() {
+      Some(len) if len <= mem.map_len => Ok(mem),
+      _ => Err(()),
+    }
+  }
+}
+
+impl<T> ShmMem<T> where T: Copy {
+  /// Removes a named POSIX shared memory object. Existing mappings remain
+  /// valid until they are dropped.
+  pub fn unlink_named(name: &str) -> Result<(), ()> {
+    let c_name = CString::new(name).map_err(|_| ())?;
+    if unsafe { libc::shm_unlink(c_name.as_ptr()) } != 0 {
+      return Err(());
+    }
+    Ok(())
+  }
+
+  pub fn header(&self) -> &ShmHeader {
+    &self.header
+  }
+
+  fn buf(&self) -> *mut T {
+    unsafe { self.base.offset(SHM_HEADER_LEN as isize) as *mut T }
+  }
+}
+
+impl<T> TagMem for ShmMem<T> where T: Copy {
+  fn tag(&self) -> &'static str {
+    self.acct.tag()
+  }
+
+  fn set_tag(&mut self, tag: &'static str) {
+    self.acct.set_tag(tag);
+  }
+}
+
+impl<T> AsRawFd for ShmMem<T> where T: Copy {
+  fn as_raw_fd(&self) -> RawFd {
+    self.fd
+  }
+}
+
+impl<T> ReadOnlyMem<T> for ShmMem<T> where T: Copy {
+  unsafe fn as_ptr(&self) -> *const T {
+    self.buf()
+  }
+
+  fn as_slice(&self) -> &[T] {
+    unsafe { from_raw_parts(self.buf(), self.header.flat_len()) }
+  }
+
+  fn as_bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts(self.buf() as *const u8, self.header.flat_len() * size_of::<T>()) }
+  }
+}
+
+impl<T> Mem<T> for ShmMem<T> where T: Copy {
+  unsafe fn as_mut_ptr(&mut self) -> *mut T {
+    self.buf()
+  }
+
+  fn as_mut_slice(&mut self) -> &mut [T] {
+    unsafe { from_raw_parts_mut(self.buf(), self.header.flat_len()) }
+  }
+
+  fn as_mut_bytes(&mut self) -> &mut [u8] {
+    unsafe { from_raw_parts_mut(self.buf() as *mut u8, self.header.flat_len() * size_of::<T>()) }
+  }
+}
+
+impl<Idx, T> MemArray<Idx, T, ShmMem<T>> where Idx: ArrayIndex, T: ToNpyDtypeDesc + ZeroBits {
+  /// Rebuilds an array from the size recorded in the shared memory header.
+  /// Fails if the recorded rank does not match `Idx`.
+  pub fn from_shm(mem: ShmMem<T>) -> Result<Self, ()> {
+    let nd_size = mem.header().nd_size();
+    if nd_size.len() != Idx::zero().to_nd().len() {
+      return Err(());
+    }
+    let size = <Idx as ArrayIndex>::from_nd(nd_size);
+    Ok(MemArray::with_memory(size, mem))
+  }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+  use super::*;
+  use arrayidx::{Index2d, Index3d};
+
+  use std::os::unix::io::{AsRawFd};
+
+  #[test]
+  fn memfd_round_trip() {
+    let mem = ShmMem::<f32>::create_memfd("memarray-test", &[3, 4]).unwrap();
+    let mut a: MemArray<Index2d, f32, ShmMem<f32>> = MemArray::from_shm(mem).unwrap();
+    a.set([2, 3], 5.0);
+    let fd = unsafe { libc::dup(a.memory().as_raw_fd()) };
+    assert!(fd >= 0);
+    let mem = unsafe { ShmMem::<f32>::from_raw_fd(fd) }.unwrap();
+    assert_eq!(mem.header().nd_size(), vec![3, 4]);
+    let b: MemArray<Index2d, f32, ShmMem<f32>> = MemArray::from_shm(mem).unwrap();
+    assert_eq!(b[[2, 3]], 5.0);
+    assert_eq!(b[[0, 0]], 0.0);
+    a.set([0, 0], 1.0);
+    assert_eq!(b[[0, 0]], 1.0);
+  }
+
+  #[test]
+  fn attach_checks_dtype_and_rank() {
+    let mem = ShmMem::<i16>::create_memfd("memarray-test", &[3, 4]).unwrap();
+    let fd = unsafe { libc::dup(mem.as_raw_fd()) };
+    assert!(unsafe { ShmMem::<f32>::from_raw_fd(fd) }.is_err());
+    let fd = unsafe { libc::dup(mem.as_raw_fd()) };
+    let mem2 = unsafe { ShmMem::<i16>::from_raw_fd(fd) }.unwrap();
+    assert!(MemArray::<Index3d, i16, ShmMem<i16>>::from_shm(mem2).is_err());
+  }
+
+  #[test]
+  fn attach_rejects_overflowing_header() {
+    let mem = ShmMem::<f64>::create_memfd("memarray-test", &[3, 4]).unwrap();
+    let mut header = *mem.header();
+    header.size[0] = 1 << 62;
+    header.size[1] = 4;
+    header.len = 1 << 62;
+    unsafe { write(mem.base as *mut ShmHeader, header) };
+    let fd = unsafe { libc::dup(mem.as_raw_fd()) };
+    assert!(unsafe { ShmMem::<f64>::from_raw_fd(fd) }.is_err());
+    // The element count fits, but not the length in bytes.
+    header.size[0] = 1 << 61;
+    header.size[1] = 1;
+    header.len = 1 << 61;
+    unsafe { write(mem.base as *mut ShmHeader, header) };
+    let fd = unsafe { libc::dup(mem.as_raw_fd()) };
+    assert!(unsafe { ShmMem::<f64>::from_raw_fd(fd) }.is_err());
+    // The length fits, but is larger than the object.
+    header.size[0] = 1 << 40;
+    header.len = 1 << 40;
+    unsafe { write(mem.base as *mut ShmHeader, header) };
+    let fd = unsafe { libc::dup(mem.as_raw_fd()) };
+    assert!(unsafe { ShmMem::<f64>::from_raw_fd(fd) }.is_err());
+  }
+
+  #[test]
+  fn create_rejects_overflowing_size() {
+    assert!(ShmMem::<f64>::create_memfd("memarray-test", &[1 << 62, 4]).is_err());
+    assert!(ShmMem::<f64>::create_memfd("memarray-test", &(usize::max_value() / 4)).is_err());
+  }
+
+  #[test]
+  fn named_round_trip() {
+    let name = format!("/memarray-test-{}", std::process::id());
+    let mut a = ShmMem::<u32>::create_named(&name, &[2, 2]).unwrap();
+    assert!(ShmMem::<u32>::create_named(&name, &[2, 2]).is_err());
+    a.as_mut_slice()[3] = 7;
+    let b = unsafe { ShmMem::<u32>::open_named(&name) }.unwrap();
+    ShmMem::<u32>::unlink_named(&name).unwrap();
+    assert_eq!(b.as_slice(), &[0, 0, 0, 7]);
+    assert!(unsafe { ShmMem::<u32>::open_named(&name) }.is_err());
+  }
+}